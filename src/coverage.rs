@@ -0,0 +1,116 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::rc::Rc;
+
+use crate::evaluator::{Environment, Function, Hooks, RuntimeError};
+use crate::lexer::{Token, TokenVariant};
+use crate::parser::{Tree, TreeNode};
+
+/// A conditional expression: how often it was reached, then how often each
+/// of its branches was taken.
+struct Branch {
+    line: u32,
+    counts: [u32; 3],
+}
+
+/// Counts how many times evaluation reached each line. Lines are those of
+/// the tokens in the tree, so a line holding only a closing parenthesis or a
+/// comment is not instrumented. A line counts once per pass, however many
+/// nodes it holds: once for the script and once for each call of a function
+/// written on it.
+pub struct Coverage {
+    lines: BTreeMap<u32, u32>,
+    /// Lines already counted by the script and by each call in progress
+    passes: Vec<HashSet<u32>>,
+    branches: Vec<Branch>,
+    /// The `?` nodes and their branches, as the index of their `Branch` and
+    /// of the count to bump
+    nodes: HashMap<*const TreeNode, (usize, usize)>,
+}
+
+impl Coverage {
+    /// Starts every line that holds part of `ast` at zero, so lines never
+    /// reached (after a runtime error, for example) show up as missed.
+    pub fn new(ast: &Tree) -> Self {
+        let mut lines = BTreeMap::new();
+        let mut branches = Vec::new();
+        let mut nodes = HashMap::new();
+        let mut pending: Vec<_> = ast.root.iter().map(Rc::clone).collect();
+        while let Some(rc) = pending.pop() {
+            let node = rc.borrow();
+            lines.insert(node.value.line, 0);
+            if let (TokenVariant::Question, Some(colon)) = (&node.value.variant, &node.right) {
+                let colon = colon.borrow();
+                if let (Some(then_branch), Some(else_branch)) = (&colon.left, &colon.right) {
+                    nodes.insert(rc.as_ptr() as *const TreeNode, (branches.len(), 0));
+                    nodes.insert(then_branch.as_ptr() as *const TreeNode, (branches.len(), 1));
+                    nodes.insert(else_branch.as_ptr() as *const TreeNode, (branches.len(), 2));
+                    branches.push(Branch {
+                        line: node.value.line,
+                        counts: [0; 3],
+                    });
+                }
+            }
+            pending.extend([&node.left, &node.right].into_iter().flatten().map(Rc::clone));
+        }
+        Self {
+            lines,
+            passes: Vec::new(),
+            branches,
+            nodes,
+        }
+    }
+
+    /// One lcov record for `source_file`.
+    pub fn lcov(&self, source_file: &str) -> String {
+        let mut out = format!("TN:\nSF:{}\n", source_file);
+        if !self.branches.is_empty() {
+            for (block, branch) in self.branches.iter().enumerate() {
+                for (i, taken) in branch.counts[1..].iter().enumerate() {
+                    // `-` marks a branch whose condition was never evaluated
+                    let taken = if branch.counts[0] == 0 { "-".to_owned() } else { taken.to_string() };
+                    out.push_str(&format!("BRDA:{},{},{},{}\n", branch.line, block, i, taken));
+                }
+            }
+            let hit = self.branches.iter().flat_map(|b| &b.counts[1..]).filter(|count| **count > 0).count();
+            out.push_str(&format!("BRF:{}\nBRH:{}\n", self.branches.len() * 2, hit));
+        }
+        for (line, count) in self.lines.iter() {
+            out.push_str(&format!("DA:{},{}\n", line, count));
+        }
+        let hit = self.lines.values().filter(|count| **count > 0).count();
+        out.push_str(&format!("LF:{}\nLH:{}\nend_of_record\n", self.lines.len(), hit));
+        out
+    }
+}
+
+impl Hooks for Coverage {
+    fn enter(&mut self, node: &TreeNode, _env: &Rc<Environment>, depth: usize) {
+        if depth == 0 {
+            self.passes.push(HashSet::new());
+        }
+        let first_in_pass = match self.passes.last_mut() {
+            Some(pass) => pass.insert(node.value.line),
+            None => true,
+        };
+        if first_in_pass {
+            *self.lines.entry(node.value.line).or_default() += 1;
+        }
+        if let Some((branch, count)) = self.nodes.get(&(node as *const TreeNode)) {
+            self.branches[*branch].counts[*count] += 1;
+        }
+    }
+
+    fn leave(&mut self, _node: &TreeNode, _env: &Rc<Environment>, depth: usize, _result: &Result<Token, Vec<RuntimeError>>) {
+        if depth == 0 {
+            self.passes.pop();
+        }
+    }
+
+    fn enter_function(&mut self, _function: &Function) {
+        self.passes.push(HashSet::new());
+    }
+
+    fn leave_function(&mut self, _function: &Function) {
+        self.passes.pop();
+    }
+}
@@ -0,0 +1,500 @@
+use core::fmt;
+
+use crate::lexer::{LosslessToken, TokenVariant};
+use crate::parser::{self, SyntaxError};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum NodeKind {
+    Root,
+    Literal,
+    Grouping,
+    Unary,
+    Binary,
+    /// An assignment such as `x = 1` or `x += 1`
+    Assignment,
+    /// `a ? b : c`
+    Conditional,
+    /// `a, b`
+    Sequence,
+    /// `x++` or `x--`
+    Postfix,
+    /// `f(a, b)`
+    Call,
+    /// `fun (a, b) { return a + b; }` or `(a, b) => a + b`
+    Function,
+    /// `[a, b]`
+    List,
+    /// `xs[i]`
+    Index,
+    /// `xs.len`
+    Property,
+    /// Tokens that do not fit the grammar, kept so that nothing is lost
+    Error,
+}
+
+impl fmt::Display for NodeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NodeKind::Root => write!(f, "ROOT"),
+            NodeKind::Literal => write!(f, "LITERAL"),
+            NodeKind::Grouping => write!(f, "GROUPING"),
+            NodeKind::Unary => write!(f, "UNARY"),
+            NodeKind::Binary => write!(f, "BINARY"),
+            NodeKind::Assignment => write!(f, "ASSIGNMENT"),
+            NodeKind::Conditional => write!(f, "CONDITIONAL"),
+            NodeKind::Sequence => write!(f, "SEQUENCE"),
+            NodeKind::Postfix => write!(f, "POSTFIX"),
+            NodeKind::Call => write!(f, "CALL"),
+            NodeKind::Function => write!(f, "FUNCTION"),
+            NodeKind::List => write!(f, "LIST"),
+            NodeKind::Index => write!(f, "INDEX"),
+            NodeKind::Property => write!(f, "PROPERTY"),
+            NodeKind::Error => write!(f, "ERROR"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Element {
+    Node(Node),
+    Token(LosslessToken),
+}
+
+/// A concrete syntax tree node. Unlike `parser::TreeNode` it keeps every token,
+/// including parentheses, whitespace and comments, so it can be printed back
+/// into exactly the source it was built from.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Node {
+    pub kind: NodeKind,
+    pub children: Vec<Element>,
+}
+
+impl Node {
+    fn new(kind: NodeKind, children: Vec<Element>) -> Self {
+        Self { kind, children }
+    }
+
+    /// Line of the first token in this node, or 1 if it has none.
+    fn line(&self) -> u32 {
+        let mut node = self;
+        loop {
+            match node.children.first() {
+                Some(Element::Node(n)) => node = n,
+                Some(Element::Token(t)) => return t.token.line,
+                None => return 1,
+            }
+        }
+    }
+
+    /// The original source text covered by this node.
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for child in self.children.iter() {
+            match child {
+                Element::Node(n) => text.push_str(&n.text()),
+                Element::Token(t) => text.push_str(&t.to_string()),
+            }
+        }
+        text
+    }
+
+    fn write_tree(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+        // Built by hand, as a formatting width can't go past u16::MAX
+        let indent = " ".repeat(depth * 2);
+        writeln!(f, "{}{}", indent, self.kind)?;
+        for child in self.children.iter() {
+            match child {
+                Element::Node(n) => n.write_tree(f, depth + 1)?,
+                Element::Token(t) => {
+                    let indent = " ".repeat((depth + 1) * 2);
+                    for trivia in t.leading.iter() {
+                        writeln!(f, "{}{} {:?}", indent, trivia.kind, trivia.text)?;
+                    }
+                    writeln!(
+                        f,
+                        "{}{} {:?} [line {}, column {}]",
+                        indent, t.token.variant, t.token.lexeme, t.token.line, t.token.column
+                    )?;
+                    for trivia in t.trailing.iter() {
+                        writeln!(f, "{}{} {:?}", indent, trivia.kind, trivia.text)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Node {
+    fn drop(&mut self) {
+        // Long chains of operators give deep trees, and freeing one
+        // recursively could overflow the stack
+        let mut orphans: Vec<Node> = Vec::new();
+        let mut children = std::mem::take(&mut self.children);
+        loop {
+            for child in children.drain(..) {
+                if let Element::Node(n) = child {
+                    orphans.push(n);
+                }
+            }
+            match orphans.pop() {
+                Some(mut n) => children = std::mem::take(&mut n.children),
+                None => break,
+            }
+        }
+    }
+}
+
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write_tree(f, 0)
+    }
+}
+
+/// Recursive descent over the same grammar as `parser::parse()`. Anything that
+/// does not parse ends up in an `Error` node, so only nesting deeper than the
+/// parser allows makes it fail. Each method hands the rest of its work to
+/// another once it knows it has any, which keeps the frames on the path of a
+/// deeply nested group small.
+struct CstParser {
+    tokens: Vec<LosslessToken>,
+    index: usize,
+    /// Counted at the same places as `Parser::depth`
+    depth: usize,
+    max_depth: usize,
+    /// Line where the nesting went past `max_depth`
+    overflow: Option<u32>,
+}
+
+impl CstParser {
+    fn peek(&self) -> &LosslessToken {
+        &self.tokens[self.index.min(self.tokens.len() - 1)]
+    }
+
+    fn at_end(&self) -> bool {
+        self.peek().token.variant == TokenVariant::Eof
+    }
+
+    fn advance(&mut self) -> Element {
+        let token = self.peek().clone();
+        if !self.at_end() {
+            self.index += 1;
+        }
+        Element::Token(token)
+    }
+
+    /// Same check as `Parser::descend()`. Past the limit the rest of the
+    /// tokens go into an `Error` node, which is returned so the recursion
+    /// unwinds at once; the tree is thrown away in that case.
+    fn descend(&mut self) -> Option<Element> {
+        self.depth += 1;
+        if self.depth <= self.max_depth {
+            return None;
+        }
+        self.depth -= 1;
+        self.overflow.get_or_insert(self.peek().token.line);
+        let mut skipped = Vec::new();
+        while !self.at_end() {
+            skipped.push(self.advance());
+        }
+        Some(Element::Node(Node::new(NodeKind::Error, skipped)))
+    }
+
+    fn comma(&mut self) -> Element {
+        let left = self.assignment();
+        if self.peek().token.variant != TokenVariant::Comma {
+            return left;
+        }
+        self.sequence(left)
+    }
+
+    fn sequence(&mut self, mut left: Element) -> Element {
+        while self.peek().token.variant == TokenVariant::Comma {
+            let comma = self.advance();
+            let right = self.assignment();
+            left = Element::Node(Node::new(NodeKind::Sequence, vec![left, comma, right]));
+        }
+        left
+    }
+
+    fn assignment(&mut self) -> Element {
+        let target = self.conditional();
+        if !self.peek().token.is_assignment() {
+            return target;
+        }
+        self.assign(target)
+    }
+
+    fn assign(&mut self, target: Element) -> Element {
+        let operator = self.advance();
+        if let Some(error) = self.descend() {
+            return error;
+        }
+        let value = self.assignment();
+        self.depth -= 1;
+        Element::Node(Node::new(NodeKind::Assignment, vec![target, operator, value]))
+    }
+
+    fn conditional(&mut self) -> Element {
+        let condition = self.expression(0);
+        if self.peek().token.variant != TokenVariant::Question {
+            return condition;
+        }
+        self.branches(condition)
+    }
+
+    fn branches(&mut self, condition: Element) -> Element {
+        let question = self.advance();
+        if let Some(error) = self.descend() {
+            return error;
+        }
+        let mut children = vec![condition, question, self.assignment()];
+        let complete = self.expect(TokenVariant::Colon, &mut children);
+        if complete {
+            children.push(self.assignment());
+        }
+        self.depth -= 1;
+        let kind = if complete { NodeKind::Conditional } else { NodeKind::Error };
+        Element::Node(Node::new(kind, children))
+    }
+
+    fn expression(&mut self, min_precedence: u32) -> Element {
+        let left = self.unary();
+        self.binary(left, min_precedence)
+    }
+
+    fn binary(&mut self, mut left: Element, min_precedence: u32) -> Element {
+        loop {
+            let token = &self.peek().token;
+            if !token.is_binary_operator() || token.get_precedence() < min_precedence {
+                return left;
+            }
+            let precedence = token.get_precedence();
+            let operator = self.advance();
+            let right = self.expression(precedence + 1);
+            left = Element::Node(Node::new(NodeKind::Binary, vec![left, operator, right]));
+        }
+    }
+
+    fn unary(&mut self) -> Element {
+        if self.peek().token.is_unary_operator() || self.peek().token.is_increment() {
+            return self.prefix();
+        }
+        self.power()
+    }
+
+    fn prefix(&mut self) -> Element {
+        let operator = self.advance();
+        if let Some(error) = self.descend() {
+            return error;
+        }
+        let operand = self.unary();
+        self.depth -= 1;
+        Element::Node(Node::new(NodeKind::Unary, vec![operator, operand]))
+    }
+
+    /// Takes the next token if it is a `variant`. Returns whether it was.
+    fn expect(&mut self, variant: TokenVariant, children: &mut Vec<Element>) -> bool {
+        if self.peek().token.variant != variant {
+            return false;
+        }
+        children.push(self.advance());
+        true
+    }
+
+    fn power(&mut self) -> Element {
+        let base = self.primary();
+        let base = self.postfix(base);
+        if self.peek().token.variant != TokenVariant::StarStar {
+            return base;
+        }
+        self.exponent(base)
+    }
+
+    fn exponent(&mut self, base: Element) -> Element {
+        let operator = self.advance();
+        if let Some(error) = self.descend() {
+            return error;
+        }
+        let exponent = self.unary();
+        self.depth -= 1;
+        Element::Node(Node::new(NodeKind::Binary, vec![base, operator, exponent]))
+    }
+
+    fn postfix(&mut self, mut base: Element) -> Element {
+        loop {
+            if self.peek().token.is_increment() {
+                let operator = self.advance();
+                base = Element::Node(Node::new(NodeKind::Postfix, vec![base, operator]));
+            } else if self.peek().token.variant == TokenVariant::LeftParen {
+                let children = vec![base, self.advance()];
+                base = self.items(children, TokenVariant::RightParen, NodeKind::Call);
+            } else if self.peek().token.variant == TokenVariant::LeftBracket {
+                base = self.index(base);
+            } else if self.peek().token.variant == TokenVariant::Dot {
+                let mut children = vec![base, self.advance()];
+                let complete = self.expect(TokenVariant::Identifier, &mut children);
+                base = Element::Node(Node::new(if complete { NodeKind::Property } else { NodeKind::Error }, children));
+            } else {
+                return base;
+            }
+        }
+    }
+
+    fn index(&mut self, list: Element) -> Element {
+        let bracket = self.advance();
+        if let Some(error) = self.descend() {
+            return error;
+        }
+        let mut children = vec![list, bracket, self.comma()];
+        let complete = self.expect(TokenVariant::RightBracket, &mut children);
+        self.depth -= 1;
+        Element::Node(Node::new(if complete { NodeKind::Index } else { NodeKind::Error }, children))
+    }
+
+    /// The items of a call or a list literal, up to the closing `close`.
+    fn items(&mut self, mut children: Vec<Element>, close: TokenVariant, kind: NodeKind) -> Element {
+        if let Some(error) = self.descend() {
+            return error;
+        }
+        if self.peek().token.variant != close {
+            children.push(self.assignment());
+            while self.expect(TokenVariant::Comma, &mut children) {
+                children.push(self.assignment());
+            }
+        }
+        self.depth -= 1;
+        let kind = if self.expect(close, &mut children) { kind } else { NodeKind::Error };
+        Element::Node(Node::new(kind, children))
+    }
+
+    /// Same lookahead as `Parser::at_arrow()`.
+    fn at_arrow(&self) -> bool {
+        let variant = |i: usize| self.tokens.get(i).map(|t| &t.token.variant);
+        let mut i = self.index + 1;
+        if variant(i) == Some(&TokenVariant::Identifier) {
+            i += 1;
+            while variant(i) == Some(&TokenVariant::Comma) && variant(i + 1) == Some(&TokenVariant::Identifier) {
+                i += 2;
+            }
+        }
+        variant(i) == Some(&TokenVariant::RightParen) && variant(i + 1) == Some(&TokenVariant::EqualGreater)
+    }
+
+    /// Both forms of function. Anything missing ends the node as an `Error`.
+    fn function(&mut self) -> Element {
+        if let Some(error) = self.descend() {
+            return error;
+        }
+        let mut children = Vec::new();
+        let arrow = !self.expect(TokenVariant::Fun, &mut children);
+        let mut complete = self.expect(TokenVariant::LeftParen, &mut children);
+        while complete && matches!(self.peek().token.variant, TokenVariant::Identifier | TokenVariant::Comma) {
+            children.push(self.advance());
+        }
+        complete = complete && self.expect(TokenVariant::RightParen, &mut children);
+        if complete && arrow {
+            children.extend([self.advance(), self.assignment()]);
+        } else if complete {
+            complete = self.expect(TokenVariant::LeftBrace, &mut children);
+            if complete && self.expect(TokenVariant::Return, &mut children) {
+                if self.peek().token.variant != TokenVariant::Semicolon {
+                    children.push(self.comma());
+                }
+                complete = self.expect(TokenVariant::Semicolon, &mut children);
+            }
+            complete = complete && self.expect(TokenVariant::RightBrace, &mut children);
+        }
+        self.depth -= 1;
+        let kind = if complete { NodeKind::Function } else { NodeKind::Error };
+        Element::Node(Node::new(kind, children))
+    }
+
+    fn group(&mut self) -> Element {
+        let paren = self.advance();
+        if let Some(error) = self.descend() {
+            return error;
+        }
+        let mut children = vec![paren, self.comma()];
+        self.depth -= 1;
+        let kind = if self.expect(TokenVariant::RightParen, &mut children) { NodeKind::Grouping } else { NodeKind::Error };
+        Element::Node(Node::new(kind, children))
+    }
+
+    fn primary(&mut self) -> Element {
+        match self.peek().token.variant {
+            TokenVariant::LeftParen if self.at_arrow() => self.function(),
+            TokenVariant::Fun => self.function(),
+            TokenVariant::LeftBracket => {
+                let children = vec![self.advance()];
+                self.items(children, TokenVariant::RightBracket, NodeKind::List)
+            }
+            TokenVariant::LeftParen => self.group(),
+            TokenVariant::Number(_)
+            | TokenVariant::Integer(_)
+            | TokenVariant::String(_)
+            | TokenVariant::Identifier
+            | TokenVariant::True
+            | TokenVariant::False
+            | TokenVariant::Nil => Element::Node(Node::new(NodeKind::Literal, vec![self.advance()])),
+            TokenVariant::Eof => Element::Node(Node::new(NodeKind::Error, Vec::new())),
+            _ => Element::Node(Node::new(NodeKind::Error, vec![self.advance()])),
+        }
+    }
+}
+
+/// Line of the first node nested deeper than `max_depth`, counted like
+/// `parser::too_deep()` does on the tree the parser builds, which has no
+/// nodes for the root or for groups.
+fn too_deep(root: &Node, max_depth: usize) -> Option<u32> {
+    let mut pending = vec![(root, 0)];
+    while let Some((node, depth)) = pending.pop() {
+        let depth = match node.kind {
+            NodeKind::Root | NodeKind::Grouping => depth,
+            _ => depth + 1,
+        };
+        if depth > max_depth {
+            return Some(node.line());
+        }
+        for child in node.children.iter() {
+            if let Element::Node(n) = child {
+                pending.push((n, depth));
+            }
+        }
+    }
+    None
+}
+
+/// Builds a concrete syntax tree from the output of `lexer::tokenize_lossless()`.
+/// Nesting too deep for the parser is a stack overflow here as well, since
+/// everything that walks the tree is recursive.
+pub fn parse(tokens: Vec<LosslessToken>) -> Result<Node, SyntaxError> {
+    parse_with_max_depth(tokens, parser::MAX_DEPTH)
+}
+
+pub fn parse_with_max_depth(tokens: Vec<LosslessToken>, max_depth: usize) -> Result<Node, SyntaxError> {
+    let mut parser = CstParser {
+        tokens,
+        index: 0,
+        depth: 0,
+        max_depth,
+        overflow: None,
+    };
+    let mut children = Vec::new();
+    if !parser.at_end() {
+        children.push(parser.comma());
+    }
+    let mut skipped = Vec::new();
+    while !parser.at_end() {
+        skipped.push(parser.advance());
+    }
+    if !skipped.is_empty() {
+        children.push(Element::Node(Node::new(NodeKind::Error, skipped)));
+    }
+    children.push(parser.advance());
+    let root = Node::new(NodeKind::Root, children);
+    match parser.overflow.or_else(|| too_deep(&root, parser.max_depth)) {
+        Some(line) => Err(SyntaxError::stack_overflow(line)),
+        None => Ok(root),
+    }
+}
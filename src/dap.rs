@@ -0,0 +1,294 @@
+use std::fs;
+use std::io::{self, BufRead};
+use std::path::Path;
+use std::process::exit;
+use std::rc::Rc;
+
+use crate::debugger::{self, Mode, Session};
+use crate::evaluator::{self, Environment, Function, Hooks, RuntimeError};
+use crate::json::Json;
+use crate::lexer::{self, Token};
+use crate::lsp::{read_message, write_message};
+use crate::parser::{self, Tree, TreeNode};
+
+/// The evaluator is single threaded, so every request is about this thread.
+const THREAD_ID: u32 = 1;
+
+struct Server<R: BufRead> {
+    input: R,
+    seq: u32,
+    program: String,
+    ast: Option<Tree>,
+    configured: bool,
+    resumed: bool,
+    session: Session,
+    /// Settings for the script and the expressions it is asked to evaluate
+    interpreter: evaluator::Interpreter,
+}
+
+impl<R: BufRead> Server<R> {
+    fn send(&mut self, kind: &str, mut fields: Vec<(&str, Json)>) {
+        self.seq += 1;
+        fields.insert(0, ("seq", self.seq.into()));
+        fields.insert(1, ("type", kind.into()));
+        write_message(&Json::object(fields));
+    }
+
+    fn event(&mut self, event: &str, body: Json) {
+        self.send("event", vec![("event", event.into()), ("body", body)]);
+    }
+
+    fn output(&mut self, category: &str, text: String) {
+        self.event(
+            "output",
+            Json::object(vec![("category", category.into()), ("output", text.into())]),
+        );
+    }
+
+    /// Reads and answers requests until one of them resumes the script.
+    /// `None` means the client hung up.
+    fn serve(&mut self) -> Option<()> {
+        self.resumed = false;
+        while !self.resumed {
+            let message = match read_message(&mut self.input) {
+                Ok(Some(message)) => message,
+                Ok(None) => return None,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    continue;
+                }
+            };
+            let command = message.get("command").as_str().unwrap_or_default().to_owned();
+            let (success, body) = match self.request(&command, message.get("arguments")) {
+                Ok(body) => (true, body),
+                Err(error) => (false, Json::object(vec![("error", Json::object(vec![("format", error.into())]))])),
+            };
+            self.send(
+                "response",
+                vec![
+                    ("request_seq", message.get("seq").clone()),
+                    ("success", success.into()),
+                    ("command", command.as_str().into()),
+                    ("body", body),
+                ],
+            );
+            match command.as_str() {
+                "initialize" => self.event("initialized", Json::object(Vec::new())),
+                "disconnect" | "terminate" => exit(0),
+                _ => {}
+            }
+        }
+        Some(())
+    }
+
+    fn launch(&mut self, arguments: &Json) -> Result<Json, String> {
+        self.program = arguments.get("program").as_str().unwrap_or_default().to_owned();
+        let file_contents = fs::read_to_string(&self.program)
+            .map_err(|_| format!("Failed to read file {}", self.program))?;
+        let (tokens, errors) = lexer::tokenize(&file_contents);
+        if let Some(e) = errors {
+            return Err(e.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("\n"));
+        }
+        let ast = parser::parse_with_max_depth(&tokens, self.interpreter.max_depth)
+            .map_err(|e| e.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("\n"))?;
+        self.ast = Some(ast);
+        self.session.mode = match arguments.get("stopOnEntry") {
+            Json::Bool(true) => Mode::Step,
+            _ => Mode::Continue,
+        };
+        Ok(Json::Null)
+    }
+
+    fn stack_trace(&self) -> Json {
+        let name = Path::new(&self.program)
+            .file_name()
+            .map_or(String::new(), |n| n.to_string_lossy().into_owned());
+        let source = Json::object(vec![("name", name.into()), ("path", self.program.as_str().into())]);
+        let frames: Vec<Json> = (self.session.frames.iter().rev().enumerate())
+            .map(|(id, frame)| {
+                Json::object(vec![
+                    ("id", id.into()),
+                    ("name", frame.expression.as_str().into()),
+                    ("line", frame.line.into()),
+                    ("column", frame.column.max(1).into()),
+                    ("source", source.clone()),
+                ])
+            })
+            .collect();
+        Json::object(vec![("totalFrames", frames.len().into()), ("stackFrames", frames.into())])
+    }
+
+    /// Handles one request, returning the response body or an error message.
+    fn request(&mut self, command: &str, arguments: &Json) -> Result<Json, String> {
+        let depth = self.session.frames.len().saturating_sub(1);
+        match command {
+            "initialize" => Ok(Json::object(vec![
+                ("supportsConfigurationDoneRequest", true.into()),
+                ("supportsEvaluateForHovers", false.into()),
+            ])),
+            "launch" => self.launch(arguments),
+            "setBreakpoints" => {
+                let lines: Vec<u32> = arguments
+                    .get("breakpoints")
+                    .as_array()
+                    .iter()
+                    .filter_map(|b| b.get("line").as_u64())
+                    .map(|line| line as u32)
+                    .collect();
+                self.session.breakpoints = lines.iter().copied().collect();
+                let breakpoints: Vec<Json> = lines
+                    .iter()
+                    .map(|line| Json::object(vec![("verified", true.into()), ("line", (*line).into())]))
+                    .collect();
+                Ok(Json::object(vec![("breakpoints", breakpoints.into())]))
+            }
+            "configurationDone" => {
+                self.configured = true;
+                self.resumed = true;
+                Ok(Json::Null)
+            }
+            "threads" => Ok(Json::object(vec![(
+                "threads",
+                vec![Json::object(vec![("id", THREAD_ID.into()), ("name", "main".into())])].into(),
+            )])),
+            "stackTrace" => Ok(self.stack_trace()),
+            "scopes" => {
+                // Each frame has two scopes, numbered from 1 so that 0 can mean none
+                let frame = arguments.get("frameId").as_u64().unwrap_or(0) as usize;
+                let scope = |name: &str, reference: usize| {
+                    Json::object(vec![
+                        ("name", name.into()),
+                        ("variablesReference", reference.into()),
+                        ("expensive", false.into()),
+                    ])
+                };
+                Ok(Json::object(vec![(
+                    "scopes",
+                    vec![scope("Locals", 2 * frame + 1), scope("Globals", 2 * frame + 2)].into(),
+                )]))
+            }
+            "variables" => {
+                let reference = arguments.get("variablesReference").as_u64().unwrap_or(0) as usize;
+                let frame = reference.checked_sub(1).and_then(|r| self.session.frames.iter().rev().nth(r / 2));
+                let bindings = match frame {
+                    Some(frame) if reference % 2 == 1 => {
+                        let operands = frame.operands.iter().map(|(name, value)| (name.to_string(), value.clone()));
+                        frame.locals().into_iter().chain(operands).collect()
+                    }
+                    Some(frame) => frame.globals(),
+                    None => Vec::new(),
+                };
+                let variables: Vec<Json> = bindings
+                    .into_iter()
+                    .map(|(name, value)| {
+                        Json::object(vec![
+                            ("name", name.into()),
+                            ("value", value.into()),
+                            ("variablesReference", 0u32.into()),
+                        ])
+                    })
+                    .collect();
+                Ok(Json::object(vec![("variables", variables.into())]))
+            }
+            "evaluate" => {
+                let expression = arguments.get("expression").as_str().unwrap_or_default();
+                // In the innermost frame unless the request names another
+                let frame = arguments.get("frameId").as_u64().unwrap_or(0) as usize;
+                let env = self.session.frames.iter().rev().nth(frame).map(|f| f.env.clone()).unwrap_or_default();
+                Ok(Json::object(vec![
+                    ("result", debugger::evaluate_source(expression, &env, &self.interpreter).into()),
+                    ("variablesReference", 0u32.into()),
+                ]))
+            }
+            "continue" | "next" | "stepIn" | "stepOut" => {
+                self.session.mode = match command {
+                    "continue" => Mode::Continue,
+                    "next" => Mode::Next(depth),
+                    "stepIn" => Mode::Step,
+                    _ => Mode::Finish(depth),
+                };
+                self.resumed = true;
+                Ok(Json::object(vec![("allThreadsContinued", true.into())]))
+            }
+            "disconnect" | "terminate" => Ok(Json::Null),
+            _ => Err(format!("Unsupported request {command}")),
+        }
+    }
+
+    fn stop(&mut self, reason: &str) {
+        self.event(
+            "stopped",
+            Json::object(vec![
+                ("reason", reason.into()),
+                ("threadId", THREAD_ID.into()),
+                ("allThreadsStopped", true.into()),
+            ]),
+        );
+        if self.serve().is_none() {
+            exit(0);
+        }
+    }
+}
+
+impl<R: BufRead> Hooks for Server<R> {
+    fn enter_function(&mut self, _function: &Function) {
+        self.session.enter_function();
+    }
+
+    fn enter(&mut self, node: &TreeNode, env: &Rc<Environment>, depth: usize) {
+        let reason = match (&self.session.mode, depth) {
+            (Mode::Continue, _) => "breakpoint",
+            (Mode::Step, 0) => "entry",
+            _ => "step",
+        };
+        if self.session.enter(node, env, depth) {
+            self.stop(reason);
+        }
+    }
+
+    fn leave(&mut self, _node: &TreeNode, _env: &Rc<Environment>, depth: usize, result: &Result<Token, Vec<RuntimeError>>) {
+        if self.session.finishing(depth) {
+            self.stop("step");
+        }
+        self.session.leave(debugger::describe(result));
+    }
+}
+
+/// Serves the Debug Adapter Protocol over stdio: waits for `launch` and
+/// `configurationDone`, runs the script, then answers until `disconnect`.
+/// Returns the process exit code.
+pub fn run(interpreter: evaluator::Interpreter) -> i32 {
+    let stdin = io::stdin();
+    let mut server = Server {
+        input: stdin.lock(),
+        seq: 0,
+        program: String::new(),
+        ast: None,
+        configured: false,
+        resumed: false,
+        session: Session::new(),
+        interpreter,
+    };
+    while !server.configured || server.ast.is_none() {
+        if server.serve().is_none() {
+            return 0;
+        }
+    }
+    let ast = server.ast.take().unwrap();
+    let exit_code = match server.interpreter.clone().evaluate_with_hooks(&ast, &mut server) {
+        Ok(output) => {
+            server.output("stdout", format!("{}\n", output.value_print()));
+            0
+        }
+        Err(e) => {
+            for error in e {
+                server.output("stderr", format!("{}\n", error));
+            }
+            70
+        }
+    };
+    server.event("exited", Json::object(vec![("exitCode", Json::Number(exit_code as f64))]));
+    server.event("terminated", Json::object(Vec::new()));
+    while server.serve().is_some() {}
+    0
+}
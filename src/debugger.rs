@@ -0,0 +1,327 @@
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+use std::process::exit;
+use std::rc::Rc;
+
+use crate::evaluator::{Environment, Function, Hooks, Interpreter, RuntimeError};
+use crate::lexer::{self, Token, TokenVariant};
+use crate::parser::{self, TreeNode};
+
+const HELP: &str = "\
+step, s              stop at the next expression, entering operands
+next, n              stop at the next expression that is not an operand of this one
+finish, f            run until this expression has a value
+continue, c          run until a breakpoint
+break, b <line>      stop when evaluation reaches a line
+delete, d <line>     remove a breakpoint
+backtrace, bt        list the expressions being evaluated, innermost first
+locals [frame]       parameters of a frame and its operands that already have a value
+globals              variables the frame's function captured where it was created
+watch, w <expr>      evaluate an expression every time the script stops
+print, p <expr>      evaluate an expression once
+quit, q              stop debugging";
+
+pub enum Mode {
+    Step,
+    Next(usize),
+    Finish(usize),
+    Continue,
+}
+
+/// An expression whose evaluation has started but not finished.
+pub struct Frame {
+    pub expression: String,
+    pub line: u32,
+    pub column: u32,
+    names: &'static [&'static str],
+    /// Operands that already have a value, named `left`, `right` or `operand`
+    pub operands: Vec<(&'static str, String)>,
+    /// The variables the expression can see
+    pub env: Rc<Environment>,
+}
+
+impl Frame {
+    /// Parameters of the function the frame is in, sorted by name.
+    pub fn locals(&self) -> Vec<(String, String)> {
+        describe_bindings(self.env.bindings())
+    }
+
+    /// Variables of the functions around that one, innermost first, without
+    /// those hidden by a parameter of the same name.
+    pub fn globals(&self) -> Vec<(String, String)> {
+        let mut seen: BTreeSet<String> = self.env.bindings().into_iter().map(|(name, _)| name).collect();
+        let mut globals = Vec::new();
+        let mut scope = self.env.enclosing();
+        while let Some(env) = scope {
+            let visible = env.bindings().into_iter().filter(|(name, _)| seen.insert(name.clone()));
+            globals.extend(describe_bindings(visible.collect()));
+            scope = env.enclosing();
+        }
+        globals
+    }
+}
+
+fn describe_bindings(bindings: Vec<(String, TokenVariant)>) -> Vec<(String, String)> {
+    bindings
+        .into_iter()
+        .map(|(name, value)| (name, Token::from((value, 0)).value_print()))
+        .collect()
+}
+
+/// Where the evaluation is and when it should stop next. Frontends feed it
+/// the evaluator hooks and decide how to talk to the user when it stops.
+pub struct Session {
+    pub breakpoints: BTreeSet<u32>,
+    /// Innermost frame last
+    pub frames: Vec<Frame>,
+    pub mode: Mode,
+    /// A function was called and its body is next
+    called: bool,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self {
+            breakpoints: BTreeSet::new(),
+            frames: Vec::new(),
+            mode: Mode::Step,
+            called: false,
+        }
+    }
+
+    /// Notes that a function's body is about to be evaluated.
+    pub fn enter_function(&mut self) {
+        self.called = true;
+    }
+
+    /// Pushes a frame for `node` and tells whether to stop before evaluating it.
+    pub fn enter(&mut self, node: &TreeNode, env: &Rc<Environment>, depth: usize) -> bool {
+        let line = node.value.line;
+        // A breakpoint stops once each time evaluation comes to its line,
+        // from an expression on another line or through a call
+        let arrived = self.called
+            || match self.frames.last() {
+                Some(parent) => parent.line != line,
+                None => true,
+            };
+        self.called = false;
+        self.frames.push(Frame {
+            expression: node.to_string(),
+            line: node.value.line,
+            column: node.value.column,
+            names: match (&node.value.variant, &node.left, &node.right) {
+                (TokenVariant::Question, _, _) => &["condition", "branch"],
+                // Arguments, then the value returned, are unnamed operands
+                (TokenVariant::LeftParen, _, _) => &["callee"],
+                (TokenVariant::Fun | TokenVariant::EqualGreater, _, _) => &[],
+                (TokenVariant::LeftBracket, None, _) => &[],
+                (TokenVariant::LeftBracket, _, _) => &["list", "index"],
+                (TokenVariant::Dot, _, _) => &["object"],
+                (_, Some(_), Some(_)) => &["left", "right"],
+                (_, None, Some(_)) => &["operand"],
+                _ => &[],
+            },
+            operands: Vec::new(),
+            env: env.clone(),
+        });
+        match self.mode {
+            Mode::Step => true,
+            Mode::Next(d) => depth <= d,
+            Mode::Finish(_) => false,
+            Mode::Continue => arrived && self.breakpoints.contains(&line),
+        }
+    }
+
+    /// Tells whether to stop now that the frame at `depth` has a value.
+    pub fn finishing(&self, depth: usize) -> bool {
+        matches!(self.mode, Mode::Finish(d) if d == depth)
+    }
+
+    /// Pops the innermost frame and hands its value to the frame below.
+    pub fn leave(&mut self, value: String) {
+        self.frames.pop();
+        if let Some(parent) = self.frames.last_mut() {
+            let name = parent.names.get(parent.operands.len()).copied().unwrap_or("operand");
+            parent.operands.push((name, value));
+        }
+    }
+}
+
+/// Drives the evaluator from commands read line by line, `gdb` style. The
+/// script stops before its first expression so breakpoints can be set.
+pub struct Debugger<R: BufRead> {
+    input: R,
+    source: Vec<String>,
+    watches: Vec<String>,
+    session: Session,
+    /// Settings for the expressions printed and watched, the script's own
+    interpreter: Interpreter,
+}
+
+impl<R: BufRead> Debugger<R> {
+    pub fn new(input: R, file_contents: &str, interpreter: Interpreter) -> Self {
+        Self {
+            input,
+            source: file_contents.lines().map(str::to_owned).collect(),
+            watches: Vec::new(),
+            session: Session::new(),
+            interpreter,
+        }
+    }
+
+    /// The variables of the innermost frame.
+    fn env(&self) -> Rc<Environment> {
+        self.session.frames.last().map(|f| f.env.clone()).unwrap_or_default()
+    }
+
+    fn show_location(&self, frame: &Frame) {
+        let text = self.source.get(frame.line as usize - 1).map_or("", |l| l.trim());
+        println!("[line {}] {}", frame.line, text);
+        println!("-> {}", frame.expression);
+        for (i, watch) in self.watches.iter().enumerate() {
+            println!("watch {}: {} = {}", i + 1, watch, evaluate_source(watch, &frame.env, &self.interpreter));
+        }
+    }
+
+    fn pause(&mut self, depth: usize) {
+        if let Some(frame) = self.session.frames.last() {
+            self.show_location(frame);
+        }
+        loop {
+            print!("(debug) ");
+            let _ = io::stdout().flush();
+            let mut line = String::new();
+            if self.input.read_line(&mut line).unwrap_or(0) == 0 {
+                // Nobody is left to type commands, let the script finish
+                self.session.breakpoints.clear();
+                self.session.mode = Mode::Continue;
+                println!();
+                return;
+            }
+            let (command, argument) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+            let argument = argument.trim();
+            match command {
+                "step" | "s" => self.session.mode = Mode::Step,
+                "next" | "n" => self.session.mode = Mode::Next(depth),
+                "finish" | "f" => self.session.mode = Mode::Finish(depth),
+                "continue" | "c" => self.session.mode = Mode::Continue,
+                "break" | "b" | "delete" | "d" => {
+                    match argument.parse::<u32>() {
+                        Ok(line) if command.starts_with('b') => {
+                            self.session.breakpoints.insert(line);
+                            println!("Breakpoint at line {}.", line);
+                        }
+                        Ok(line) if self.session.breakpoints.remove(&line) => {
+                            println!("Deleted breakpoint at line {}.", line);
+                        }
+                        Ok(line) => println!("No breakpoint at line {}.", line),
+                        Err(_) => println!("Expect a line number."),
+                    }
+                    continue;
+                }
+                "backtrace" | "bt" => {
+                    for (i, frame) in self.session.frames.iter().rev().enumerate() {
+                        println!("#{} [line {}] {}", i, frame.line, frame.expression);
+                    }
+                    continue;
+                }
+                "locals" => {
+                    let index = argument.parse::<usize>().unwrap_or(0);
+                    match self.session.frames.iter().rev().nth(index) {
+                        Some(frame) => {
+                            let locals = frame.locals();
+                            if locals.is_empty() && frame.operands.is_empty() {
+                                println!("No locals.");
+                            }
+                            for (name, value) in locals.iter() {
+                                println!("{} = {}", name, value);
+                            }
+                            for (name, value) in frame.operands.iter() {
+                                println!("{} = {}", name, value);
+                            }
+                        }
+                        None => println!("No frame #{}.", index),
+                    }
+                    continue;
+                }
+                "globals" => {
+                    let globals = self.session.frames.last().map(Frame::globals).unwrap_or_default();
+                    if globals.is_empty() {
+                        println!("No globals.");
+                    }
+                    for (name, value) in globals.iter() {
+                        println!("{} = {}", name, value);
+                    }
+                    continue;
+                }
+                "watch" | "w" if !argument.is_empty() => {
+                    self.watches.push(argument.to_owned());
+                    let value = evaluate_source(argument, &self.env(), &self.interpreter);
+                    println!("watch {}: {} = {}", self.watches.len(), argument, value);
+                    continue;
+                }
+                "print" | "p" if !argument.is_empty() => {
+                    println!("{}", evaluate_source(argument, &self.env(), &self.interpreter));
+                    continue;
+                }
+                "quit" | "q" => exit(0),
+                "help" | "h" => {
+                    println!("{}", HELP);
+                    continue;
+                }
+                "" => continue,
+                _ => {
+                    println!("Unknown command: {}. Try 'help'.", line.trim());
+                    continue;
+                }
+            }
+            return;
+        }
+    }
+}
+
+impl<R: BufRead> Hooks for Debugger<R> {
+    fn enter_function(&mut self, _function: &Function) {
+        self.session.enter_function();
+    }
+
+    fn enter(&mut self, node: &TreeNode, env: &Rc<Environment>, depth: usize) {
+        if self.session.enter(node, env, depth) {
+            self.pause(depth);
+        }
+    }
+
+    fn leave(&mut self, _node: &TreeNode, _env: &Rc<Environment>, depth: usize, result: &Result<Token, Vec<RuntimeError>>) {
+        let value = describe(result);
+        if self.session.finishing(depth) {
+            println!("Value: {}", value);
+            self.pause(depth);
+        }
+        self.session.leave(value);
+    }
+}
+
+pub fn describe(result: &Result<Token, Vec<RuntimeError>>) -> String {
+    match result {
+        Ok(token) => token.value_print(),
+        Err(errors) => format!("<error: {}>", errors[0].message()),
+    }
+}
+
+/// Evaluates a watch or print expression where the variables in `env` are
+/// visible and describes the outcome. Assigning to one changes it for the
+/// script as well. `interpreter` holds the script's settings, such as
+/// `--strict`, so the expression runs the way the script does.
+pub fn evaluate_source(source: &str, env: &Rc<Environment>, interpreter: &Interpreter) -> String {
+    let (tokens, errors) = lexer::tokenize(source);
+    if let Some(e) = errors {
+        return format!("<error: {}>", e[0].message());
+    }
+    match parser::parse_with_max_depth(&tokens, interpreter.max_depth) {
+        Ok(ast) => match interpreter.evaluate_in(&ast, env) {
+            Ok(token) => token.value_print(),
+            Err(e) => format!("<error: {}>", e[0].message()),
+        },
+        Err(e) => format!("<error: {}>", e[0].message()),
+    }
+}
@@ -1,15 +1,35 @@
 use crate::lexer::{Token, TokenVariant};
-use crate::parser::Tree;
+use crate::parser::{self, Tree, TreeNode};
 use core::fmt;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ptr;
 use std::rc::Rc;
 use RuntimeErrorVariant::*;
 use TokenVariant::*;
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 enum RuntimeErrorVariant {
     MustBeNumber,
     MustBeNumbers,
     MustBeNumbersOrStrings,
+    MustBeInteger,
+    MustBeIntegers,
+    StackOverflow,
+    UndefinedVariable(std::string::String),
+    UnsupportedOperands(std::string::String),
+    MalformedExpression,
+    DivisionByZero,
+    IntegerOverflow,
+    NegativeShift,
+    NotCallable,
+    WrongArity(usize, usize),
+    NotIndexable,
+    IndexMustBeInteger,
+    IndexOutOfBounds,
+    NoMethods,
+    UndefinedProperty(std::string::String),
 }
 
 impl fmt::Display for RuntimeErrorVariant {
@@ -18,6 +38,39 @@ impl fmt::Display for RuntimeErrorVariant {
             MustBeNumber => write!(f, "Operand must be a number."),
             MustBeNumbers => write!(f, "Operands must be numbers."),
             MustBeNumbersOrStrings => write!(f, "Operands must be two numbers or two strings."),
+            MustBeInteger => write!(f, "Operand must be an integer."),
+            MustBeIntegers => write!(f, "Operands must be integers."),
+            StackOverflow => write!(f, "Stack overflow."),
+            UndefinedVariable(name) => write!(f, "Undefined variable '{name}'."),
+            UnsupportedOperands(operator) => write!(f, "Unsupported operands for '{operator}'."),
+            MalformedExpression => write!(f, "Malformed expression."),
+            DivisionByZero => write!(f, "Division by zero."),
+            IntegerOverflow => write!(f, "Integer overflow."),
+            NegativeShift => write!(f, "Shift amount must not be negative."),
+            NotCallable => write!(f, "Can only call functions and classes."),
+            WrongArity(expected, got) => write!(f, "Expected {expected} arguments but got {got}."),
+            NotIndexable => write!(f, "Only lists can be indexed."),
+            IndexMustBeInteger => write!(f, "Index must be an integer."),
+            IndexOutOfBounds => write!(f, "Index out of bounds."),
+            NoMethods => write!(f, "Only lists have methods."),
+            UndefinedProperty(name) => write!(f, "Undefined property '{name}'."),
+        }
+    }
+}
+
+/// Where a call was when the error happened. `function` is `None` for the
+/// top level of the script.
+#[derive(Debug, PartialEq)]
+struct StackFrame {
+    line: u32,
+    function: Option<std::string::String>,
+}
+
+impl fmt::Display for StackFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.function {
+            Some(name) => write!(f, "[line {}] in {}()", self.line, name),
+            None => write!(f, "[line {}] in script", self.line),
         }
     }
 }
@@ -25,12 +78,28 @@ impl fmt::Display for RuntimeErrorVariant {
 #[derive(Debug)]
 pub struct RuntimeError {
     variant: RuntimeErrorVariant,
-    line: u32,
+    /// Innermost call first, the script last
+    trace: Vec<StackFrame>,
 }
 
 impl fmt::Display for RuntimeError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}\n[line {}]", self.variant, self.line)
+        write!(f, "{}", self.variant)?;
+        // Runaway recursion would print the same frame a thousand times
+        let mut frames = self.trace.iter().peekable();
+        while let Some(frame) = frames.next() {
+            write!(f, "\n{}", frame)?;
+            let mut repeated = 0;
+            while frames.next_if_eq(&frame).is_some() {
+                repeated += 1;
+            }
+            match repeated {
+                0 => {}
+                1 => write!(f, "\n{}", frame)?,
+                _ => write!(f, "\n[previous frame repeated {} more times]", repeated)?,
+            }
+        }
+        Ok(())
     }
 }
 
@@ -38,153 +107,750 @@ impl RuntimeError {
     fn new(variant: RuntimeErrorVariant, line: u32) -> Self {
         Self {
             variant,
-            line
+            trace: vec![StackFrame { line, function: None }],
         }
     }
+
+    /// Records that the code which failed ran inside `function`, called on
+    /// `line` of its caller.
+    fn called_from(mut self, function: &str, line: u32) -> Self {
+        if let Some(outermost) = self.trace.last_mut() {
+            outermost.function = Some(function.to_owned());
+        }
+        self.trace.push(StackFrame { line, function: None });
+        self
+    }
+
+    pub fn message(&self) -> std::string::String {
+        self.variant.to_string()
+    }
 }
 
-pub fn evaluate(ast: &mut Tree) -> Result<Token, Vec<RuntimeError>> {
-    let mut final_token = Token::from((Eof, 0)); //TODO Replace with Option<Token>
-    let mut errors = Vec::new();
-    match &ast.root {
-        Some(root_node) => {
-            let left = &root_node.borrow().left;
-            let right = &root_node.borrow().right;
-            if let Some(l) = left {
-                let mut left_tree = Tree::new();
-                left_tree.root = Some(Rc::clone(l));
-                match evaluate(&mut left_tree) {
-                    Ok(ft) => l.borrow_mut().value = ft,
-                    Err(e) => return Err(e)
-                }
+/// Functions have no names, so this is what they print as and what stack
+/// traces call them.
+const LAMBDA: &str = "lambda";
+
+/// How tools refer to a function, which has no name of its own: `lambda@3`
+/// for one written on line 3.
+pub fn function_label(line: u32) -> std::string::String {
+    format!("{LAMBDA}@{line}")
+}
+
+/// The variables a function can see: its parameters, then those of the
+/// functions it was created in. The script itself has none.
+#[derive(Default)]
+pub struct Environment {
+    values: RefCell<HashMap<std::string::String, TokenVariant>>,
+    enclosing: Option<Rc<Environment>>,
+}
+
+impl Environment {
+    fn get(&self, name: &str) -> Option<TokenVariant> {
+        match self.values.borrow().get(name) {
+            Some(value) => Some(value.clone()),
+            None => self.enclosing.as_ref()?.get(name),
+        }
+    }
+
+    /// Changes the innermost variable called `name`, if there is one.
+    fn assign(&self, name: &str, value: TokenVariant) -> bool {
+        if let Some(slot) = self.values.borrow_mut().get_mut(name) {
+            *slot = value;
+            return true;
+        }
+        self.enclosing.as_ref().is_some_and(|e| e.assign(name, value))
+    }
+
+    /// The variables bound in this scope alone, sorted by name.
+    pub fn bindings(&self) -> Vec<(std::string::String, TokenVariant)> {
+        let mut bindings: Vec<_> = self.values.borrow().iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+        bindings.sort_by(|a, b| a.0.cmp(&b.0));
+        bindings
+    }
+
+    pub fn enclosing(&self) -> Option<&Rc<Environment>> {
+        self.enclosing.as_ref()
+    }
+}
+
+/// A function value. Its body is the expression it returns, evaluated in
+/// a fresh environment for each call, enclosed by the one it was created in.
+pub struct Function {
+    /// Where the `fun` or `=>` is
+    line: u32,
+    parameters: Vec<std::string::String>,
+    /// `None` when the function returns nothing, as in `fun () {}`
+    body: Option<Rc<RefCell<TreeNode>>>,
+    closure: Rc<Environment>,
+}
+
+impl Function {
+    /// What tools call the function, see `function_label()`.
+    pub fn label(&self) -> std::string::String {
+        function_label(self.line)
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<fn {LAMBDA}>")
+    }
+}
+
+impl fmt::Debug for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self}")
+    }
+}
+
+impl PartialEq for Function {
+    fn eq(&self, other: &Self) -> bool {
+        ptr::eq(self, other)
+    }
+}
+
+/// A list value. Lists are shared and changed in place, so `xs.push(1)` is
+/// seen by everything that holds `xs`.
+pub struct List {
+    pub items: RefCell<Vec<TokenVariant>>,
+}
+
+impl fmt::Debug for List {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The items could hold the list itself
+        write!(f, "<list of {}>", self.items.borrow().len())
+    }
+}
+
+impl PartialEq for List {
+    fn eq(&self, other: &Self) -> bool {
+        ptr::eq(self, other)
+    }
+}
+
+/// The methods of lists and how many arguments each takes.
+const METHODS: [(&str, usize); 7] = [
+    ("push", 1),
+    ("pop", 0),
+    ("len", 0),
+    ("insert", 2),
+    ("remove", 1),
+    ("slice", 2),
+    ("contains", 1),
+];
+
+/// A method bound to its list, as in `xs.push`.
+#[derive(Clone)]
+pub struct Method {
+    list: Rc<List>,
+    name: &'static str,
+    arity: usize,
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+impl fmt::Debug for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self}")
+    }
+}
+
+impl PartialEq for Method {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.list, &other.list) && self.name == other.name
+    }
+}
+
+/// `index` as a position in a list of `len` items. With `end`, one past the
+/// last item is allowed too, as `insert` and `slice` need.
+fn position(index: &TokenVariant, len: usize, end: bool) -> Result<usize, RuntimeErrorVariant> {
+    let i = match index {
+        Integer(i) => *i,
+        Number(n) if n.fract() == 0.0 => *n as i64,
+        _ => return Err(IndexMustBeInteger),
+    };
+    match usize::try_from(i) {
+        Ok(i) if i < len || (end && i == len) => Ok(i),
+        _ => Err(IndexOutOfBounds),
+    }
+}
+
+/// The list and position that `list[index]` refers to.
+fn element(list: Token, index: Token) -> Result<(Rc<List>, usize), RuntimeError> {
+    match list.variant {
+        List(list) => {
+            let len = list.items.borrow().len();
+            let i = position(&index.variant, len, false).map_err(|e| RuntimeError::new(e, index.line))?;
+            Ok((list, i))
+        }
+        _ => Err(RuntimeError::new(NotIndexable, list.line)),
+    }
+}
+
+/// Runs a list method. The arguments are already checked against its arity.
+fn call_method(method: &Method, arguments: &[TokenVariant]) -> Result<TokenVariant, RuntimeErrorVariant> {
+    let mut items = method.list.items.borrow_mut();
+    let len = items.len();
+    Ok(match (method.name, arguments) {
+        ("push", [item]) => {
+            items.push(item.clone());
+            Nil
+        }
+        ("pop", []) => items.pop().ok_or(IndexOutOfBounds)?,
+        ("len", []) => Number(len as f64),
+        ("insert", [index, item]) => {
+            items.insert(position(index, len, true)?, item.clone());
+            Nil
+        }
+        ("remove", [index]) => items.remove(position(index, len, false)?),
+        ("slice", [start, end]) => {
+            let (start, end) = (position(start, len, true)?, position(end, len, true)?);
+            if start > end {
+                return Err(IndexOutOfBounds);
             }
-            if let Some(r) = right {
-                let mut right_tree = Tree::new();
-                right_tree.root = Some(Rc::clone(r));
-                match evaluate(&mut right_tree) {
-                    Ok(ft) => r.borrow_mut().value = ft,
-                    Err(e) => return Err(e)
-                }
+            List(Rc::new(self::List {
+                items: RefCell::new(items[start..end].to_vec()),
+            }))
+        }
+        ("contains", [item]) => {
+            if items.iter().any(|i| equals(i, item)) {
+                True
+            } else {
+                False
             }
-            match (left, right) {
-                (Some(l), Some(r)) => {
-                    match (
-                        &root_node.borrow().value.variant,
-                        &l.borrow().value.variant,
-                        &r.borrow().value.variant,
-                    ) {
-                        (Plus, Number(a), Number(b)) => {
-                            final_token = Token::from((Number(a + b), 0));
-                        }
-                        (Minus, Number(a), Number(b)) => {
-                            final_token = Token::from((Number(a - b), 0));
-                        }
-                        (Star, Number(a), Number(b)) => {
-                            final_token = Token::from((Number(a * b), 0));
-                        }
-                        (Slash, Number(a), Number(b)) => {
-                            final_token = Token::from((Number(a / b), 0));
-                        }
-                        (Plus, String(a), String(b)) => {
-                            final_token = Token::from((String(format!("{a}{b}")), 0));
-                        }
-                        (Less, Number(a), Number(b)) => {
-                            final_token = Token::from((if a < b { True } else { False }, 0));
-                        }
-                        (LessEqual, Number(a), Number(b)) => {
-                            final_token = Token::from((if a <= b { True } else { False }, 0));
-                        }
-                        (Greater, Number(a), Number(b)) => {
-                            final_token = Token::from((if a > b { True } else { False }, 0));
-                        }
-                        (GreaterEqual, Number(a), Number(b)) => {
-                            final_token = Token::from((if a >= b { True } else { False }, 0));
-                        }
-                        (EqualEqual, Number(a), Number(b)) => {
-                            final_token = Token::from((if a == b { True } else { False }, 0));
-                        }
-                        (BangEqual, Number(a), Number(b)) => {
-                            final_token = Token::from((if a != b { True } else { False }, 0));
-                        }
-                        (EqualEqual, String(a), String(b)) => {
-                            final_token = Token::from((if a == b { True } else { False }, 0));
-                        }
-                        (BangEqual, String(a), String(b)) => {
-                            final_token = Token::from((if a != b { True } else { False }, 0));
-                        }
-                        (EqualEqual, Number(_), String(_)) | (EqualEqual, String(_), Number(_)) => {
-                            final_token = Token::from((False, 0));
-                        }
-                        (BangEqual, Number(_), String(_)) | (BangEqual, String(_), Number(_)) => {
-                            final_token = Token::from((True, 0));
-                        }
-                        (Plus, _, _) => {
-                            errors.push(RuntimeError::new(MustBeNumbersOrStrings, l.borrow().value.line));
-                        }
-                        (Minus, _, _) => {
-                            errors.push(RuntimeError::new(MustBeNumbersOrStrings, l.borrow().value.line));
-                        }
-                        (Star, Number(_), _) => {
-                            errors.push(RuntimeError::new(MustBeNumbers, r.borrow().value.line));
-                        }
-                        (Star, _, _) => {
-                            errors.push(RuntimeError::new(MustBeNumbers, l.borrow().value.line));
-                        }
-                        (Slash, Number(_), _) => {
-                            errors.push(RuntimeError::new(MustBeNumbers, r.borrow().value.line));
-                        }
-                        (Slash, _, _) => {
-                            errors.push(RuntimeError::new(MustBeNumbers, l.borrow().value.line));
-                        }
-                        (Less, Number(_), _) => {
-                            errors.push(RuntimeError::new(MustBeNumbers, r.borrow().value.line));
-                        }
-                        (Less, _, _) => {
-                            errors.push(RuntimeError::new(MustBeNumbers, l.borrow().value.line));
-                        }
-                        (LessEqual, Number(_), _) => {
-                            errors.push(RuntimeError::new(MustBeNumbers, r.borrow().value.line));
-                        }
-                        (LessEqual, _, _) => {
-                            errors.push(RuntimeError::new(MustBeNumbers, l.borrow().value.line));
-                        }
-                        (Greater, Number(_), _) => {
-                            errors.push(RuntimeError::new(MustBeNumbers, r.borrow().value.line));
-                        }
-                        (Greater, _, _) => {
-                            errors.push(RuntimeError::new(MustBeNumbers, l.borrow().value.line));
-                        }
-                        (GreaterEqual, Number(_), _) => {
-                            errors.push(RuntimeError::new(MustBeNumbers, r.borrow().value.line));
-                        }
-                        (GreaterEqual, _, _) => {
-                            errors.push(RuntimeError::new(MustBeNumbers, l.borrow().value.line));
-                        }
-                        _ => {
-                            panic!("Unhandled operation");
-                        }
-                    }
-                }
-                (None, Some(v)) => {
-                    match (&root_node.borrow().value.variant, &v.borrow().value.variant) {
-                        (Bang, Nil) | (Bang, False) => final_token = Token::from((True, 0)),
-                        (Bang, Number(_)) | (Bang, True) => final_token = Token::from((False, 0)),
-                        (Minus, Number(x)) => final_token = Token::from((Number(-x), 0)),
-                        (Minus, _) => errors.push(RuntimeError::new(MustBeNumber, v.borrow().value.line)),
-                        _ => {
-                            panic!("Unhandled operation");
-                        }
-                    }
+        }
+        _ => return Err(MalformedExpression),
+    })
+}
+
+/// Where an assignment stores its result.
+enum Place {
+    Variable(std::string::String),
+    Element(Rc<List>, usize),
+}
+
+/// `nil` and `false` are falsey, every other value is truthy.
+fn is_truthy(value: &TokenVariant) -> bool {
+    !matches!(value, Nil | False)
+}
+
+/// Values of different kinds are never equal. Numbers, strings, booleans
+/// and `nil` compare by value; functions and lists compare by identity.
+fn is_equal(a: &TokenVariant, b: &TokenVariant) -> bool {
+    match (a, b) {
+        (Number(a), Number(b)) => a == b,
+        (Function(a), Function(b)) => Rc::ptr_eq(a, b),
+        (List(a), List(b)) => Rc::ptr_eq(a, b),
+        (Method(a), Method(b)) => a == b,
+        (Integer(a), Integer(b)) => a == b,
+        (String(a), String(b)) => a == b,
+        (True, True) | (False, False) | (Nil, Nil) => true,
+        _ => false,
+    }
+}
+
+/// What `==` means: an integer and a float are compared as floats.
+fn equals(a: &TokenVariant, b: &TokenVariant) -> bool {
+    let (mut a, mut b) = (a.clone(), b.clone());
+    promote(&mut a, &mut b);
+    is_equal(&a, &b)
+}
+
+/// Turns the integer of a mixed pair of operands into a float, so that
+/// arithmetic and comparisons between them happen in floating point.
+fn promote(a: &mut TokenVariant, b: &mut TokenVariant) {
+    match (&a, &b) {
+        (Integer(x), Number(_)) => *a = Number(*x as f64),
+        (Number(_), Integer(y)) => *b = Number(*y as f64),
+        _ => {}
+    }
+}
+
+/// Arithmetic and bitwise operations on two integers, which must not
+/// overflow. Division truncates toward zero, and so do negative powers.
+fn integer_arithmetic(operator: &TokenVariant, a: i64, b: i64) -> Result<i64, RuntimeErrorVariant> {
+    let result = match operator {
+        Plus => a.checked_add(b),
+        Minus => a.checked_sub(b),
+        Star => a.checked_mul(b),
+        StarStar => return integer_power(a, b),
+        Ampersand => Some(a & b),
+        Pipe => Some(a | b),
+        Caret => Some(a ^ b),
+        LessLess | GreaterGreater if b < 0 => return Err(NegativeShift),
+        LessLess if a == 0 => Some(0),
+        // Shifting out any bit that matters is an overflow
+        LessLess => u32::try_from(b)
+            .ok()
+            .and_then(|b| a.checked_shl(b).filter(|n| n.checked_shr(b) == Some(a))),
+        GreaterGreater => Some(a >> b.min(63)),
+        _ if b == 0 => return Err(DivisionByZero),
+        // The only remainder that overflows, `i64::MIN % -1`, is 0
+        Percent => Some(a.wrapping_rem(b)),
+        _ => a.checked_div(b),
+    };
+    result.ok_or(IntegerOverflow)
+}
+
+/// `a ** b` on integers. A negative power is `1 / a ** -b`, which truncates
+/// to 0 unless `a` is 1 or -1.
+fn integer_power(a: i64, b: i64) -> Result<i64, RuntimeErrorVariant> {
+    match (a, b) {
+        (0, 0) => Ok(1),
+        (0, b) if b < 0 => Err(DivisionByZero),
+        (0 | 1, _) => Ok(a),
+        (-1, b) => Ok(if b % 2 == 0 { 1 } else { -1 }),
+        (_, b) if b < 0 => Ok(0),
+        (_, b) => u32::try_from(b).ok().and_then(|b| a.checked_pow(b)).ok_or(IntegerOverflow),
+    }
+}
+
+/// Lets a frontend such as the debugger follow the evaluation. A node is
+/// entered before its operands are evaluated and left once its own value is
+/// known. `env` holds the variables the node can see and `depth` is 0 for
+/// the root of the tree. A function is entered once its arguments are bound
+/// and left when its body has a value, so calls can be told apart from the
+/// nodes that make them.
+pub trait Hooks {
+    fn enter_function(&mut self, _function: &Function) {}
+    fn leave_function(&mut self, _function: &Function) {}
+    fn enter(&mut self, _node: &TreeNode, _env: &Rc<Environment>, _depth: usize) {}
+    fn leave(
+        &mut self,
+        _node: &TreeNode,
+        _env: &Rc<Environment>,
+        _depth: usize,
+        _result: &Result<Token, Vec<RuntimeError>>,
+    ) {
+    }
+}
+
+struct NoHooks;
+
+impl Hooks for NoHooks {}
+
+/// Several frontends at once, called in order.
+impl Hooks for Vec<&mut dyn Hooks> {
+    fn enter_function(&mut self, function: &Function) {
+        for hooks in self.iter_mut() {
+            hooks.enter_function(function);
+        }
+    }
+
+    fn leave_function(&mut self, function: &Function) {
+        for hooks in self.iter_mut() {
+            hooks.leave_function(function);
+        }
+    }
+
+    fn enter(&mut self, node: &TreeNode, env: &Rc<Environment>, depth: usize) {
+        for hooks in self.iter_mut() {
+            hooks.enter(node, env, depth);
+        }
+    }
+
+    fn leave(&mut self, node: &TreeNode, env: &Rc<Environment>, depth: usize, result: &Result<Token, Vec<RuntimeError>>) {
+        for hooks in self.iter_mut() {
+            hooks.leave(node, env, depth, result);
+        }
+    }
+}
+
+/// Settings that stay the same for a whole evaluation.
+#[derive(Clone)]
+pub struct Interpreter {
+    /// Deepest nesting evaluated before reporting "Stack overflow."
+    pub max_depth: usize,
+    /// Dividing by zero is a runtime error instead of giving `inf`, `-inf`
+    /// or `NaN`. Either way NaN is unequal to everything, itself included,
+    /// every comparison with it is false and it prints as `NaN`.
+    pub strict: bool,
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Self {
+            max_depth: parser::MAX_DEPTH,
+            strict: false,
+        }
+    }
+
+    pub fn evaluate(&self, ast: &Tree) -> Result<Token, Vec<RuntimeError>> {
+        self.evaluate_with_hooks(ast, &mut NoHooks)
+    }
+
+    pub fn evaluate_with_hooks(&self, ast: &Tree, hooks: &mut dyn Hooks) -> Result<Token, Vec<RuntimeError>> {
+        match &ast.root {
+            Some(root) => self.evaluate_at(root, &Rc::new(Environment::default()), 0, hooks),
+            None => Ok(Token::from((Nil, 0))),
+        }
+    }
+
+    /// Evaluates `ast` where the variables in `env` are visible, as the
+    /// debugger does for the expressions it is asked to print.
+    pub fn evaluate_in(&self, ast: &Tree, env: &Rc<Environment>) -> Result<Token, Vec<RuntimeError>> {
+        match &ast.root {
+            Some(root) => self.evaluate_at(root, env, 0, &mut NoHooks),
+            None => Ok(Token::from((Nil, 0))),
+        }
+    }
+
+    /// Evaluates the tree under `node`. Nodes are only read, so a function
+    /// body can be evaluated once per call.
+    fn evaluate_at(
+        &self,
+        node: &Rc<RefCell<TreeNode>>,
+        env: &Rc<Environment>,
+        depth: usize,
+        hooks: &mut dyn Hooks,
+    ) -> Result<Token, Vec<RuntimeError>> {
+        if depth >= self.max_depth {
+            return Err(vec![RuntimeError::new(StackOverflow, node.borrow().value.line)]);
+        }
+        hooks.enter(&node.borrow(), env, depth);
+        let result = self.evaluate_node(&node.borrow(), env, depth, hooks);
+        hooks.leave(&node.borrow(), env, depth, &result);
+        result
+    }
+
+    /// Evaluates the condition of `a ? b : c`, then only the branch it picks.
+    fn evaluate_conditional(
+        &self,
+        node: &TreeNode,
+        env: &Rc<Environment>,
+        depth: usize,
+        hooks: &mut dyn Hooks,
+    ) -> Result<Token, Vec<RuntimeError>> {
+        let (condition, branches) = match (&node.left, &node.right) {
+            (Some(condition), Some(branches)) => (condition, branches.borrow()),
+            _ => return Err(vec![RuntimeError::new(MalformedExpression, node.value.line)]),
+        };
+        let value = self.evaluate_at(condition, env, depth + 1, hooks)?;
+        let branch = if is_truthy(&value.variant) { &branches.left } else { &branches.right };
+        match branch {
+            Some(branch) => self.evaluate_at(branch, env, depth + 1, hooks),
+            None => Err(vec![RuntimeError::new(MalformedExpression, node.value.line)]),
+        }
+    }
+
+    /// Evaluates the callee and the arguments in order, then the body of the
+    /// function with its parameters bound to the arguments.
+    fn call(
+        &self,
+        node: &TreeNode,
+        env: &Rc<Environment>,
+        depth: usize,
+        hooks: &mut dyn Hooks,
+    ) -> Result<Token, Vec<RuntimeError>> {
+        let line = node.value.line;
+        let callee = match &node.left {
+            Some(callee) => self.evaluate_at(callee, env, depth + 1, hooks)?,
+            None => return Err(vec![RuntimeError::new(MalformedExpression, line)]),
+        };
+        let mut arguments = Vec::new();
+        for argument in parser::list(&node.right) {
+            arguments.push(self.evaluate_at(&argument, env, depth + 1, hooks)?.variant);
+        }
+        let function = match callee.variant {
+            Function(function) => function,
+            Method(method) if arguments.len() != method.arity => {
+                return Err(vec![RuntimeError::new(WrongArity(method.arity, arguments.len()), line)]);
+            }
+            Method(method) => {
+                let result = call_method(&method, &arguments).map_err(|e| vec![RuntimeError::new(e, line)])?;
+                return Ok(Token::from((result, line)));
+            }
+            _ => return Err(vec![RuntimeError::new(NotCallable, line)]),
+        };
+        if arguments.len() != function.parameters.len() {
+            let arity = WrongArity(function.parameters.len(), arguments.len());
+            return Err(vec![RuntimeError::new(arity, line)]);
+        }
+        let scope = Rc::new(Environment {
+            values: RefCell::new(function.parameters.iter().cloned().zip(arguments).collect()),
+            enclosing: Some(Rc::clone(&function.closure)),
+        });
+        hooks.enter_function(&function);
+        let result = match &function.body {
+            Some(body) => match self.evaluate_at(body, &scope, depth + 1, hooks) {
+                Ok(value) => Ok(Token { line, ..value }),
+                Err(e) => Err(e.into_iter().map(|e| e.called_from(LAMBDA, line)).collect()),
+            },
+            None => Ok(Token::from((Nil, line))),
+        };
+        hooks.leave_function(&function);
+        result
+    }
+
+    /// The variable or list element an assignment changes, and its value
+    /// before the change.
+    fn place(
+        &self,
+        target: &Rc<RefCell<TreeNode>>,
+        env: &Rc<Environment>,
+        depth: usize,
+        hooks: &mut dyn Hooks,
+    ) -> Result<(Place, Token), Vec<RuntimeError>> {
+        let t = target.borrow();
+        match (&t.value.variant, &t.left, &t.right) {
+            (LeftBracket, Some(list), Some(index)) => {
+                let list = self.evaluate_at(list, env, depth + 1, hooks)?;
+                let index = self.evaluate_at(index, env, depth + 1, hooks)?;
+                let (list, i) = element(list, index).map_err(|e| vec![e])?;
+                let old = list.items.borrow()[i].clone();
+                Ok((Place::Element(list, i), Token::from((old, t.value.line))))
+            }
+            _ => {
+                let old = self.evaluate_at(target, env, depth + 1, hooks)?;
+                Ok((Place::Variable(t.value.lexeme.clone()), old))
+            }
+        }
+    }
+
+    /// `x = 1`, `xs[0] += 1`, `++x` and `x++`: finds what the target refers
+    /// to and reads it, then evaluates the value, then stores the result.
+    /// Postfix operators give the old value.
+    fn assign(
+        &self,
+        node: &TreeNode,
+        env: &Rc<Environment>,
+        depth: usize,
+        hooks: &mut dyn Hooks,
+    ) -> Result<Token, Vec<RuntimeError>> {
+        let line = node.value.line;
+        let (target, value) = match (&node.left, &node.right) {
+            (Some(target), value) => (target, value.as_ref()),
+            (None, Some(target)) => (target, None),
+            (None, None) => return Err(vec![RuntimeError::new(MalformedExpression, line)]),
+        };
+        let (place, old) = self.place(target, env, depth, hooks)?;
+        let (operator, value) = match (&node.value.variant, value) {
+            (Equal, Some(value)) => (Equal, self.evaluate_at(value, env, depth + 1, hooks)?),
+            (PlusEqual, Some(value)) => (Plus, self.evaluate_at(value, env, depth + 1, hooks)?),
+            (MinusEqual, Some(value)) => (Minus, self.evaluate_at(value, env, depth + 1, hooks)?),
+            (StarEqual, Some(value)) => (Star, self.evaluate_at(value, env, depth + 1, hooks)?),
+            (SlashEqual, Some(value)) => (Slash, self.evaluate_at(value, env, depth + 1, hooks)?),
+            (PlusPlus | MinusMinus, _) => {
+                let one = match old.variant {
+                    Integer(_) => Integer(1),
+                    Number(_) => Number(1.0),
+                    _ => return Err(vec![RuntimeError::new(MustBeNumber, old.line)]),
+                };
+                let operator = if node.value.variant == PlusPlus { Plus } else { Minus };
+                (operator, Token::from((one, line)))
+            }
+            _ => return Err(vec![RuntimeError::new(MalformedExpression, line)]),
+        };
+        let new = match operator {
+            Equal => Token { line, ..value },
+            operator => {
+                let operator = Token { variant: operator, ..node.value.clone() };
+                self.binary(&operator, old.clone(), value).map_err(|e| vec![e])?
+            }
+        };
+        match place {
+            Place::Variable(name) => {
+                if !env.assign(&name, new.variant.clone()) {
+                    return Err(vec![RuntimeError::new(UndefinedVariable(name), line)]);
                 }
-                (None, None) => final_token = root_node.borrow().value.clone(),
-                _ => panic!("Invalid tree structure. (Should have gotten a syntax error)"),
             }
+            // The value may have changed the list, as in `xs[0] = xs.pop()`
+            Place::Element(list, i) => match list.items.borrow_mut().get_mut(i) {
+                Some(item) => *item = new.variant.clone(),
+                None => return Err(vec![RuntimeError::new(IndexOutOfBounds, line)]),
+            },
+        }
+        match (&node.value.variant, &node.left) {
+            (PlusPlus | MinusMinus, Some(_)) => Ok(Token { line, ..old }),
+            _ => Ok(new),
+        }
+    }
+
+    fn evaluate_node(
+        &self,
+        node: &TreeNode,
+        env: &Rc<Environment>,
+        depth: usize,
+        hooks: &mut dyn Hooks,
+    ) -> Result<Token, Vec<RuntimeError>> {
+        // Results take the line of the operator that produced them
+        let line = node.value.line;
+        match (&node.value.variant, &node.left, &node.right) {
+            (Question, _, _) => self.evaluate_conditional(node, env, depth, hooks),
+            (LeftParen, _, _) => self.call(node, env, depth, hooks),
+            (Fun | EqualGreater, parameters, body) => {
+                let function = self::Function {
+                    line,
+                    parameters: parser::list(parameters).iter().map(|p| p.borrow().value.lexeme.clone()).collect(),
+                    body: body.clone(),
+                    closure: Rc::clone(env),
+                };
+                Ok(Token::from((Function(Rc::new(function)), line)))
+            }
+            (Equal | PlusEqual | MinusEqual | StarEqual | SlashEqual | PlusPlus | MinusMinus, _, _) => {
+                self.assign(node, env, depth, hooks)
+            }
+            (LeftBracket, None, _) => self.list(node, env, depth, hooks),
+            (LeftBracket, Some(_), Some(_)) => self.index(node, env, depth, hooks),
+            (Dot, Some(_), Some(_)) => self.property(node, env, depth, hooks),
+            (_, Some(l), Some(r)) => {
+                let a = self.evaluate_at(l, env, depth + 1, hooks)?;
+                let b = self.evaluate_at(r, env, depth + 1, hooks)?;
+                self.binary(&node.value, a, b).map_err(|e| vec![e])
+            }
+            (_, None, Some(v)) => {
+                let v = self.evaluate_at(v, env, depth + 1, hooks)?;
+                self.unary(&node.value, v).map_err(|e| vec![e])
+            }
+            (Identifier, None, None) => match env.get(&node.value.lexeme) {
+                Some(value) => Ok(Token::from((value, line))),
+                None => Err(vec![RuntimeError::new(UndefinedVariable(node.value.lexeme.clone()), line)]),
+            },
+            (_, None, None) => Ok(node.value.clone()),
+            (_, Some(_), None) => Err(vec![RuntimeError::new(MalformedExpression, line)]),
+        }
+    }
+
+    /// A list literal, `[a, b]`: a new list each time it is evaluated.
+    fn list(
+        &self,
+        node: &TreeNode,
+        env: &Rc<Environment>,
+        depth: usize,
+        hooks: &mut dyn Hooks,
+    ) -> Result<Token, Vec<RuntimeError>> {
+        let mut values = Vec::new();
+        for item in parser::list(&node.right) {
+            values.push(self.evaluate_at(&item, env, depth + 1, hooks)?.variant);
         }
-        None => todo!(),
+        let list = self::List {
+            items: RefCell::new(values),
+        };
+        Ok(Token::from((List(Rc::new(list)), node.value.line)))
+    }
+
+    /// `xs[i]`, reading an element.
+    fn index(
+        &self,
+        node: &TreeNode,
+        env: &Rc<Environment>,
+        depth: usize,
+        hooks: &mut dyn Hooks,
+    ) -> Result<Token, Vec<RuntimeError>> {
+        let (Some(list), Some(index)) = (&node.left, &node.right) else {
+            return Err(vec![RuntimeError::new(MalformedExpression, node.value.line)]);
+        };
+        let list = self.evaluate_at(list, env, depth + 1, hooks)?;
+        let index = self.evaluate_at(index, env, depth + 1, hooks)?;
+        let (list, i) = element(list, index).map_err(|e| vec![e])?;
+        let item = list.items.borrow()[i].clone();
+        Ok(Token::from((item, node.value.line)))
     }
-    if !errors.is_empty() {
-        Err(errors)
-    } else {
-        Ok(final_token)
+
+    /// `xs.name`, a method bound to the list it was looked up on.
+    fn property(
+        &self,
+        node: &TreeNode,
+        env: &Rc<Environment>,
+        depth: usize,
+        hooks: &mut dyn Hooks,
+    ) -> Result<Token, Vec<RuntimeError>> {
+        let line = node.value.line;
+        let (Some(object), Some(name)) = (&node.left, &node.right) else {
+            return Err(vec![RuntimeError::new(MalformedExpression, line)]);
+        };
+        let object = self.evaluate_at(object, env, depth + 1, hooks)?;
+        let name = &name.borrow().value.lexeme;
+        match (object.variant, METHODS.iter().find(|(m, _)| m == name)) {
+            (List(list), Some((name, arity))) => Ok(Token::from((Method(self::Method { list, name, arity: *arity }), line))),
+            (List(_), None) => Err(vec![RuntimeError::new(UndefinedProperty(name.clone()), line)]),
+            _ => Err(vec![RuntimeError::new(NoMethods, object.line)]),
+        }
     }
+
+    fn binary(&self, operator: &Token, mut a: Token, mut b: Token) -> Result<Token, RuntimeError> {
+        let line = operator.line;
+        // The right operand of a comma is its value, not something to promote
+        if operator.variant == Comma {
+            return Ok(Token::from((b.variant, line)));
+        }
+        promote(&mut a.variant, &mut b.variant);
+        let result = match (&operator.variant, &a.variant, &b.variant) {
+            (Plus, Number(a), Number(b)) => Number(a + b),
+            (Minus, Number(a), Number(b)) => Number(a - b),
+            (Star, Number(a), Number(b)) => Number(a * b),
+            (Slash | Percent | TildeSlash, Number(_), Number(b)) if self.strict && *b == 0.0 => {
+                return Err(RuntimeError::new(DivisionByZero, line));
+            }
+            (StarStar, Number(a), Number(b)) if self.strict && *a == 0.0 && *b < 0.0 => {
+                return Err(RuntimeError::new(DivisionByZero, line));
+            }
+            (Slash, Number(a), Number(b)) => Number(a / b),
+            (Percent, Number(a), Number(b)) => Number(a % b),
+            (StarStar, Number(a), Number(b)) => Number(a.powf(*b)),
+            (TildeSlash, Number(a), Number(b)) => Number((a / b).trunc()),
+            (
+                operator @ (Plus | Minus | Star | Slash | Percent | StarStar | TildeSlash | Ampersand | Pipe | Caret
+                | LessLess | GreaterGreater),
+                Integer(a),
+                Integer(b),
+            ) => match integer_arithmetic(operator, *a, *b) {
+                Ok(n) => Integer(n),
+                Err(e) => return Err(RuntimeError::new(e, line)),
+            },
+            (Plus, String(a), String(b)) => String(format!("{a}{b}")),
+            (Less, Number(a), Number(b)) => if a < b { True } else { False },
+            (LessEqual, Number(a), Number(b)) => if a <= b { True } else { False },
+            (Greater, Number(a), Number(b)) => if a > b { True } else { False },
+            (GreaterEqual, Number(a), Number(b)) => if a >= b { True } else { False },
+            (Less, Integer(a), Integer(b)) => if a < b { True } else { False },
+            (LessEqual, Integer(a), Integer(b)) => if a <= b { True } else { False },
+            (Greater, Integer(a), Integer(b)) => if a > b { True } else { False },
+            (GreaterEqual, Integer(a), Integer(b)) => if a >= b { True } else { False },
+            (EqualEqual, a, b) => if equals(a, b) { True } else { False },
+            (BangEqual, a, b) => if equals(a, b) { False } else { True },
+            (Plus | Minus, _, _) => return Err(RuntimeError::new(MustBeNumbersOrStrings, a.line)),
+            (Ampersand | Pipe | Caret | LessLess | GreaterGreater, Integer(_), _) => {
+                return Err(RuntimeError::new(MustBeIntegers, b.line));
+            }
+            (Ampersand | Pipe | Caret | LessLess | GreaterGreater, _, _) => {
+                return Err(RuntimeError::new(MustBeIntegers, a.line));
+            }
+            (
+                Star | Slash | Percent | StarStar | TildeSlash | Less | LessEqual | Greater | GreaterEqual,
+                Number(_) | Integer(_),
+                _,
+            ) => return Err(RuntimeError::new(MustBeNumbers, b.line)),
+            (Star | Slash | Percent | StarStar | TildeSlash | Less | LessEqual | Greater | GreaterEqual, _, _) => {
+                return Err(RuntimeError::new(MustBeNumbers, a.line));
+            }
+            (_, _, _) => return Err(RuntimeError::new(UnsupportedOperands(operator.lexeme.clone()), line)),
+        };
+        Ok(Token::from((result, line)))
+    }
+
+    fn unary(&self, operator: &Token, v: Token) -> Result<Token, RuntimeError> {
+        let line = operator.line;
+        let result = match (&operator.variant, &v.variant) {
+            (Bang, v) => if is_truthy(v) { False } else { True },
+            (Minus, Number(x)) => Number(-x),
+            (Minus, Integer(x)) => match x.checked_neg() {
+                Some(n) => Integer(n),
+                None => return Err(RuntimeError::new(IntegerOverflow, line)),
+            },
+            (Minus, _) => return Err(RuntimeError::new(MustBeNumber, v.line)),
+            (Tilde, Integer(x)) => Integer(!x),
+            (Tilde, _) => return Err(RuntimeError::new(MustBeInteger, v.line)),
+            (_, _) => return Err(RuntimeError::new(UnsupportedOperands(operator.lexeme.clone()), line)),
+        };
+        Ok(Token::from((result, line)))
+    }
+}
+
+pub fn evaluate(ast: &Tree) -> Result<Token, Vec<RuntimeError>> {
+    Interpreter::new().evaluate(ast)
 }
@@ -0,0 +1,249 @@
+use crate::cst::{Element, Node, NodeKind};
+use crate::lexer::{LosslessToken, TokenVariant, TriviaKind};
+
+const INDENT: &str = "    ";
+
+fn is_token(e: Option<&Element>, variant: TokenVariant) -> bool {
+    matches!(e, Some(Element::Token(t)) if t.token.variant == variant)
+}
+
+/// The lexeme an element starts with, looking through nested nodes.
+fn first_lexeme(mut e: &Element) -> &str {
+    loop {
+        match e {
+            Element::Token(t) => return &t.token.lexeme,
+            Element::Node(n) => match n.children.first() {
+                Some(child) => e = child,
+                None => return "",
+            },
+        }
+    }
+}
+
+/// Re-emits a concrete syntax tree in the canonical style: one space around
+/// binary operators, none after unary operators (unless the operand starts
+/// with a sign that would run into them) or inside parentheses,
+/// comments kept where they were and lines broken by a comment continued
+/// with one level of indentation per open parenthesis (at least one).
+struct Formatter {
+    out: String,
+    depth: usize,
+    at_line_start: bool,
+    line_break: bool,
+    in_expression: bool,
+}
+
+impl Formatter {
+    fn new() -> Self {
+        Self {
+            out: String::new(),
+            depth: 0,
+            at_line_start: true,
+            line_break: false,
+            in_expression: false,
+        }
+    }
+
+    fn newline(&mut self) {
+        let trimmed = self.out.trim_end_matches([' ', '\t']).len();
+        self.out.truncate(trimmed);
+        self.out.push('\n');
+        self.at_line_start = true;
+    }
+
+    fn blank_line(&mut self) {
+        if !self.at_line_start {
+            self.newline();
+        }
+        if !self.out.is_empty() && !self.out.ends_with("\n\n") {
+            self.newline();
+        }
+    }
+
+    fn write(&mut self, text: &str, space_before: bool) {
+        if self.line_break {
+            self.newline();
+            self.line_break = false;
+        }
+        if self.at_line_start {
+            if self.in_expression {
+                self.out.push_str(&INDENT.repeat(self.depth.max(1)));
+            }
+        } else if space_before {
+            self.out.push(' ');
+        }
+        self.out.push_str(text);
+        self.at_line_start = false;
+    }
+
+    fn token(&mut self, t: &LosslessToken, space_before: bool) {
+        let mut newlines = 0;
+        let mut after_comment = false;
+        for trivia in t.leading.iter() {
+            match trivia.kind {
+                TriviaKind::Newline => {
+                    newlines += 1;
+                    self.line_break |= after_comment;
+                }
+                TriviaKind::LineComment | TriviaKind::BlockComment => {
+                    if newlines > 0 || self.out.trim_start_matches('\u{feff}').is_empty() {
+                        // Comments on their own line stay on their own line
+                        if newlines > 1 {
+                            self.blank_line();
+                        } else if !self.at_line_start {
+                            self.line_break = true;
+                        }
+                        self.write(&trivia.text, false);
+                    } else {
+                        self.write(&trivia.text, true);
+                    }
+                    self.line_break |= trivia.kind == TriviaKind::LineComment;
+                    newlines = 0;
+                    after_comment = true;
+                }
+                TriviaKind::ByteOrderMark => self.out.push_str(&trivia.text),
+                TriviaKind::Skipped => self.write(&trivia.text, true),
+                TriviaKind::Whitespace => {}
+            }
+        }
+        if newlines > 1 && !self.in_expression && !t.token.lexeme.is_empty() {
+            self.blank_line();
+            self.line_break = false;
+        }
+        if !t.token.lexeme.is_empty() {
+            self.write(&t.token.lexeme, space_before);
+        }
+        for trivia in t.trailing.iter() {
+            match trivia.kind {
+                TriviaKind::LineComment => {
+                    self.write(&trivia.text, true);
+                    self.line_break = true;
+                }
+                TriviaKind::BlockComment | TriviaKind::Skipped => self.write(&trivia.text, true),
+                _ => {}
+            }
+        }
+    }
+
+    fn element(&mut self, e: &Element, space_before: bool) {
+        match e {
+            Element::Node(n) => self.node(n, space_before),
+            Element::Token(t) => self.token(t, space_before),
+        }
+    }
+
+    fn node(&mut self, n: &Node, space_before: bool) {
+        match n.kind {
+            NodeKind::Root => {
+                for child in n.children.iter() {
+                    if let Element::Token(eof) = child {
+                        // Comments after the expression are not continuation lines
+                        self.in_expression = false;
+                        self.depth = 0;
+                        self.token(eof, false);
+                    } else {
+                        self.element(child, true);
+                        self.in_expression = true;
+                    }
+                }
+            }
+            NodeKind::Binary | NodeKind::Assignment | NodeKind::Conditional => {
+                for (i, child) in n.children.iter().enumerate() {
+                    self.element(child, i > 0 || space_before);
+                }
+                self.in_expression = true;
+            }
+            NodeKind::Sequence => {
+                // `a, b`: the comma sticks to the operand before it
+                for (i, child) in n.children.iter().enumerate() {
+                    self.element(child, i == 2 || (i == 0 && space_before));
+                }
+                self.in_expression = true;
+            }
+            NodeKind::Unary | NodeKind::Postfix | NodeKind::Index | NodeKind::Property => {
+                for (i, child) in n.children.iter().enumerate() {
+                    // `- -1` and `- --a` would lex differently without the space
+                    let merges = i == 1
+                        && n.kind == NodeKind::Unary
+                        && matches!(first_lexeme(&n.children[0]), "-" | "+")
+                        && first_lexeme(child).starts_with(['-', '+']);
+                    self.element(child, merges || (i == 0 && space_before));
+                }
+            }
+            NodeKind::Call | NodeKind::List => {
+                // `f(a, b)` and `[a, b]`: like a group, with a space after each comma
+                let open = if n.kind == NodeKind::Call { 2 } else { 1 };
+                let last = n.children.len() - 1;
+                for (i, child) in n.children.iter().enumerate() {
+                    if i == open {
+                        self.depth += 1;
+                    }
+                    if i == last && i >= open {
+                        self.depth -= 1;
+                    }
+                    let after_comma = is_token(n.children.get(i.wrapping_sub(1)), TokenVariant::Comma);
+                    self.element(child, after_comma || (i == 0 && space_before));
+                }
+            }
+            NodeKind::Function => {
+                // `fun (a, b) { return a + b; }` and `(a, b) => a + b`
+                for (i, child) in n.children.iter().enumerate() {
+                    let previous = n.children.get(i.wrapping_sub(1));
+                    let space = match child {
+                        _ if i == 0 => space_before,
+                        Element::Token(t) => match t.token.variant {
+                            TokenVariant::Comma | TokenVariant::Semicolon | TokenVariant::RightParen => false,
+                            TokenVariant::RightBrace => !is_token(previous, TokenVariant::LeftBrace),
+                            _ => !is_token(previous, TokenVariant::LeftParen),
+                        },
+                        Element::Node(_) => true,
+                    };
+                    self.element(child, space);
+                }
+            }
+            NodeKind::Grouping => {
+                let last = n.children.len() - 1;
+                for (i, child) in n.children.iter().enumerate() {
+                    if i == 1 {
+                        self.depth += 1;
+                    }
+                    if i == last {
+                        self.depth -= 1;
+                    }
+                    self.element(child, i == 0 && space_before);
+                }
+            }
+            NodeKind::Literal => {
+                for child in n.children.iter() {
+                    self.element(child, space_before);
+                }
+            }
+            NodeKind::Error => {
+                // Nothing canonical to say about code that does not parse
+                for (i, child) in n.children.iter().enumerate() {
+                    self.element(child, i > 0 || space_before);
+                }
+            }
+        }
+        if n.kind != NodeKind::Root {
+            self.in_expression = true;
+        }
+    }
+}
+
+/// Formats a whole file. The result always ends with exactly one newline.
+pub fn format(root: &Node) -> String {
+    let mut formatter = Formatter::new();
+    formatter.node(root, false);
+    if !formatter.at_line_start {
+        formatter.newline();
+    }
+    let mut out = formatter.out;
+    while out.ends_with("\n\n") {
+        out.pop();
+    }
+    if out.trim_start_matches('\u{feff}') == "\n" {
+        out.pop();
+    }
+    out
+}
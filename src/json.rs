@@ -0,0 +1,431 @@
+use core::fmt;
+
+/// Just enough JSON for the editor protocols spoken over stdio.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn object(fields: Vec<(&str, Json)>) -> Self {
+        Json::Object(fields.into_iter().map(|(k, v)| (k.to_owned(), v)).collect())
+    }
+
+    pub fn get(&self, key: &str) -> &Json {
+        match self {
+            Json::Object(fields) => fields
+                .iter()
+                .find(|(k, _)| k == key)
+                .map_or(&Json::Null, |(_, v)| v),
+            _ => &Json::Null,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Json::Number(n) if *n >= 0.0 && n.fract() == 0.0 => Some(*n as u64),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> &[Json] {
+        match self {
+            Json::Array(items) => items,
+            _ => &[],
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        *self == Json::Null
+    }
+
+    pub fn parse(text: &str) -> Result<Json, JsonError> {
+        let mut parser = JsonParser {
+            chars: text.chars().collect(),
+            index: 0,
+        };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        match parser.index < parser.chars.len() {
+            true => Err(JsonError::new("Trailing characters after JSON value.")),
+            false => Ok(value),
+        }
+    }
+}
+
+impl From<&str> for Json {
+    fn from(s: &str) -> Self {
+        Json::String(s.to_owned())
+    }
+}
+
+impl From<String> for Json {
+    fn from(s: String) -> Self {
+        Json::String(s)
+    }
+}
+
+impl From<bool> for Json {
+    fn from(b: bool) -> Self {
+        Json::Bool(b)
+    }
+}
+
+impl From<u32> for Json {
+    fn from(n: u32) -> Self {
+        Json::Number(n as f64)
+    }
+}
+
+impl From<usize> for Json {
+    fn from(n: usize) -> Self {
+        Json::Number(n as f64)
+    }
+}
+
+impl From<Vec<Json>> for Json {
+    fn from(items: Vec<Json>) -> Self {
+        Json::Array(items)
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{c}")?,
+        }
+    }
+    write!(f, "\"")
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{b}"),
+            Json::Number(n) if !n.is_finite() => write!(f, "null"),
+            Json::Number(n) => write!(f, "{n}"),
+            Json::String(s) => write_string(f, s),
+            Json::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{item}")?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{value}")?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct JsonError {
+    message: String,
+}
+
+impl JsonError {
+    fn new(message: &str) -> Self {
+        Self {
+            message: message.to_owned(),
+        }
+    }
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid JSON: {}", self.message)
+    }
+}
+
+/// Whether `text` follows the JSON grammar for numbers, which is stricter
+/// than Rust's: no leading zeros, and digits on both sides of the point.
+fn is_number(text: &str) -> bool {
+    let digits = |s: &str| s.len() - s.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let mut rest = text.strip_prefix('-').unwrap_or(text);
+    match digits(rest) {
+        0 => return false,
+        n if n > 1 && rest.starts_with('0') => return false,
+        n => rest = &rest[n..],
+    }
+    if let Some(fraction) = rest.strip_prefix('.') {
+        match digits(fraction) {
+            0 => return false,
+            n => rest = &fraction[n..],
+        }
+    }
+    if let Some(exponent) = rest.strip_prefix(['e', 'E']) {
+        let exponent = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+        match digits(exponent) {
+            0 => return false,
+            n => rest = &exponent[n..],
+        }
+    }
+    rest.is_empty()
+}
+
+struct JsonParser {
+    chars: Vec<char>,
+    index: usize,
+}
+
+impl JsonParser {
+    fn skip_whitespace(&mut self) {
+        while self.index < self.chars.len() && self.chars[self.index].is_whitespace() {
+            self.index += 1;
+        }
+    }
+
+    fn next(&mut self) -> Result<char, JsonError> {
+        let c = self.chars.get(self.index).copied();
+        self.index += 1;
+        c.ok_or_else(|| JsonError::new("Unexpected end of input."))
+    }
+
+    fn expect_word(&mut self, word: &str, value: Json) -> Result<Json, JsonError> {
+        for expected in word.chars() {
+            if self.next()? != expected {
+                return Err(JsonError::new("Unexpected literal."));
+            }
+        }
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Json, JsonError> {
+        self.skip_whitespace();
+        match self.chars.get(self.index) {
+            Some('n') => self.expect_word("null", Json::Null),
+            Some('t') => self.expect_word("true", Json::Bool(true)),
+            Some('f') => self.expect_word("false", Json::Bool(false)),
+            Some('"') => self.string().map(Json::String),
+            Some('[') => {
+                self.index += 1;
+                let mut items = Vec::new();
+                self.skip_whitespace();
+                if self.chars.get(self.index) == Some(&']') {
+                    self.index += 1;
+                    return Ok(Json::Array(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    self.skip_whitespace();
+                    match self.next()? {
+                        ',' => {}
+                        ']' => return Ok(Json::Array(items)),
+                        _ => return Err(JsonError::new("Expect ',' or ']' in array.")),
+                    }
+                }
+            }
+            Some('{') => {
+                self.index += 1;
+                let mut fields = Vec::new();
+                self.skip_whitespace();
+                if self.chars.get(self.index) == Some(&'}') {
+                    self.index += 1;
+                    return Ok(Json::Object(fields));
+                }
+                loop {
+                    self.skip_whitespace();
+                    if self.chars.get(self.index) != Some(&'"') {
+                        return Err(JsonError::new("Expect string key in object."));
+                    }
+                    let key = self.string()?;
+                    self.skip_whitespace();
+                    if self.next()? != ':' {
+                        return Err(JsonError::new("Expect ':' after key."));
+                    }
+                    fields.push((key, self.value()?));
+                    self.skip_whitespace();
+                    match self.next()? {
+                        ',' => {}
+                        '}' => return Ok(Json::Object(fields)),
+                        _ => return Err(JsonError::new("Expect ',' or '}' in object.")),
+                    }
+                }
+            }
+            Some(c) if *c == '-' || c.is_ascii_digit() => {
+                let start = self.index;
+                while self.index < self.chars.len()
+                    && matches!(self.chars[self.index], '0'..='9' | '-' | '+' | '.' | 'e' | 'E')
+                {
+                    self.index += 1;
+                }
+                let text: String = self.chars[start..self.index].iter().collect();
+                match is_number(&text) {
+                    true => text.parse().map(Json::Number).map_err(|_| JsonError::new("Invalid number.")),
+                    false => Err(JsonError::new("Invalid number.")),
+                }
+            }
+            Some(_) => Err(JsonError::new("Unexpected character.")),
+            None => Err(JsonError::new("Unexpected end of input.")),
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, JsonError> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = self.next()?.to_digit(16);
+            code = code * 16 + digit.ok_or_else(|| JsonError::new("Invalid unicode escape."))?;
+        }
+        Ok(code)
+    }
+
+    fn string(&mut self) -> Result<String, JsonError> {
+        self.index += 1;
+        let mut s = String::new();
+        loop {
+            match self.next()? {
+                '"' => return Ok(s),
+                '\\' => match self.next()? {
+                    '"' => s.push('"'),
+                    '\\' => s.push('\\'),
+                    '/' => s.push('/'),
+                    'b' => s.push('\u{8}'),
+                    'f' => s.push('\u{c}'),
+                    'n' => s.push('\n'),
+                    'r' => s.push('\r'),
+                    't' => s.push('\t'),
+                    'u' => {
+                        let mut code = self.hex4()?;
+                        if (0xD800..0xDC00).contains(&code) {
+                            // Characters outside the BMP arrive as a surrogate pair
+                            if self.next()? != '\\' || self.next()? != 'u' {
+                                return Err(JsonError::new("Unpaired surrogate."));
+                            }
+                            let low = self.hex4()?;
+                            if !(0xDC00..0xE000).contains(&low) {
+                                return Err(JsonError::new("Unpaired surrogate."));
+                            }
+                            code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+                        }
+                        s.push(char::from_u32(code).unwrap_or('\u{fffd}'));
+                    }
+                    _ => return Err(JsonError::new("Invalid escape.")),
+                },
+                c => s.push(c),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Json;
+
+    fn parse(text: &str) -> Json {
+        Json::parse(text).unwrap_or_else(|e| panic!("{text}: {e}"))
+    }
+
+    fn parse_error(text: &str) -> String {
+        match Json::parse(text) {
+            Ok(value) => panic!("{text} parsed as {value}"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn parses_values() {
+        assert_eq!(parse(" null "), Json::Null);
+        assert_eq!(parse("true"), Json::Bool(true));
+        assert_eq!(parse("[]"), Json::Array(Vec::new()));
+        assert_eq!(parse("{}"), Json::Object(Vec::new()));
+        assert_eq!(
+            parse(r#"{"a": [1, false], "b": {"c": null}}"#),
+            Json::object(vec![
+                ("a", vec![Json::Number(1.0), false.into()].into()),
+                ("b", Json::object(vec![("c", Json::Null)])),
+            ])
+        );
+    }
+
+    #[test]
+    fn parses_escapes() {
+        assert_eq!(parse(r#""\"\\\/\b\f\n\r\t""#), Json::from("\"\\/\u{8}\u{c}\n\r\t"));
+        assert_eq!(parse(r#""\u00e9\u4e2d""#), Json::from("é中"));
+    }
+
+    #[test]
+    fn parses_surrogate_pairs() {
+        assert_eq!(parse(r#""\ud83d\ude00""#), Json::from("😀"));
+        assert_eq!(parse(r#""\uD834\uDD1E""#), Json::from("𝄞"));
+        // A low surrogate on its own is not a character
+        assert_eq!(parse(r#""\udc00""#), Json::from("\u{fffd}"));
+    }
+
+    #[test]
+    fn parses_numbers() {
+        for (text, value) in [("0", 0.0), ("-0", -0.0), ("42", 42.0), ("-1.5", -1.5), ("1e3", 1000.0), ("2.5E-1", 0.25), ("1e+2", 100.0)] {
+            assert_eq!(parse(text), Json::Number(value), "{text}");
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        for text in ["", "nul", "tru", "[1,", "[1 2]", "{\"a\" 1}", "{1: 2}", "{\"a\": 1,}", "\"open", "1 2", "@"] {
+            parse_error(text);
+        }
+        assert_eq!(parse_error(r#""\x""#), "Invalid JSON: Invalid escape.");
+        assert_eq!(parse_error(r#""\u12g4""#), "Invalid JSON: Invalid unicode escape.");
+        assert_eq!(parse_error(r#""\ud83d""#), "Invalid JSON: Unpaired surrogate.");
+        assert_eq!(parse_error(r#""\ud83d\u0041""#), "Invalid JSON: Unpaired surrogate.");
+    }
+
+    #[test]
+    fn rejects_malformed_numbers() {
+        for text in ["-", "01", "-01", "1.", "-.5", "1e", "1e+", "1-2", "--1", "1.2.3"] {
+            assert_eq!(parse_error(text), "Invalid JSON: Invalid number.", "{text}");
+        }
+    }
+
+    #[test]
+    fn serializes_strings_with_escapes() {
+        let value = Json::from("\"quoted\" \\ \n\r\t \u{1} é 😀");
+        assert_eq!(value.to_string(), r#""\"quoted\" \\ \n\r\t \u0001 é 😀""#);
+    }
+
+    #[test]
+    fn serializes_numbers() {
+        assert_eq!(Json::Number(3.0).to_string(), "3");
+        assert_eq!(Json::Number(-0.25).to_string(), "-0.25");
+        // JSON has no way to write these
+        assert_eq!(Json::Number(f64::NAN).to_string(), "null");
+        assert_eq!(Json::Number(f64::INFINITY).to_string(), "null");
+    }
+
+    #[test]
+    fn round_trips() {
+        let text = r#"{"id":1,"params":{"text":"a\nb\u0000","items":[true,null,-2.5]}}"#;
+        assert_eq!(parse(text).to_string(), text);
+    }
+}
@@ -1,10 +1,14 @@
 use core::fmt;
 use std::iter;
+use std::rc::Rc;
+
+use crate::evaluator::{Function, List, Method};
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum TokenVariant {
     Equal,
     EqualEqual,
+    EqualGreater,
     Bang,
     BangEqual,
     Less,
@@ -15,22 +19,51 @@ pub enum TokenVariant {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Dot,
     Comma,
     Semicolon,
+    Question,
+    Colon,
     Plus,
     Minus,
     Star,
     Slash,
+    Percent,
+    StarStar,
+    TildeSlash,
+    Ampersand,
+    Pipe,
+    Caret,
+    Tilde,
+    LessLess,
+    GreaterGreater,
+    PlusEqual,
+    MinusEqual,
+    StarEqual,
+    SlashEqual,
+    PlusPlus,
+    MinusMinus,
     Eof,
 
     Number(f64),
+    Integer(i64),
     String(String),
+    /// Only ever produced by the evaluator
+    Function(Rc<Function>),
+    /// Only ever produced by the evaluator
+    List(Rc<List>),
+    /// Only ever produced by the evaluator, as in `xs.push`
+    Method(Method),
     Identifier,
     Comment,
+    BlockComment,
 
     And,
+    Break,
     Class,
+    Continue,
     Else,
     False,
     For,
@@ -54,17 +87,37 @@ impl fmt::Display for TokenVariant {
             TokenVariant::RightParen => write!(f, "RIGHT_PAREN"),
             TokenVariant::LeftBrace => write!(f, "LEFT_BRACE"),
             TokenVariant::RightBrace => write!(f, "RIGHT_BRACE"),
+            TokenVariant::LeftBracket => write!(f, "LEFT_BRACKET"),
+            TokenVariant::RightBracket => write!(f, "RIGHT_BRACKET"),
             TokenVariant::Comma => write!(f, "COMMA"),
             TokenVariant::Dot => write!(f, "DOT"),
             TokenVariant::Minus => write!(f, "MINUS"),
             TokenVariant::Plus => write!(f, "PLUS"),
             TokenVariant::Semicolon => write!(f, "SEMICOLON"),
+            TokenVariant::Question => write!(f, "QUESTION"),
+            TokenVariant::Colon => write!(f, "COLON"),
             TokenVariant::Slash => write!(f, "SLASH"),
             TokenVariant::Star => write!(f, "STAR"),
+            TokenVariant::Percent => write!(f, "PERCENT"),
+            TokenVariant::StarStar => write!(f, "STAR_STAR"),
+            TokenVariant::TildeSlash => write!(f, "TILDE_SLASH"),
+            TokenVariant::Ampersand => write!(f, "AMPERSAND"),
+            TokenVariant::Pipe => write!(f, "PIPE"),
+            TokenVariant::Caret => write!(f, "CARET"),
+            TokenVariant::Tilde => write!(f, "TILDE"),
+            TokenVariant::LessLess => write!(f, "LESS_LESS"),
+            TokenVariant::GreaterGreater => write!(f, "GREATER_GREATER"),
+            TokenVariant::PlusEqual => write!(f, "PLUS_EQUAL"),
+            TokenVariant::MinusEqual => write!(f, "MINUS_EQUAL"),
+            TokenVariant::StarEqual => write!(f, "STAR_EQUAL"),
+            TokenVariant::SlashEqual => write!(f, "SLASH_EQUAL"),
+            TokenVariant::PlusPlus => write!(f, "PLUS_PLUS"),
+            TokenVariant::MinusMinus => write!(f, "MINUS_MINUS"),
             TokenVariant::Bang => write!(f, "BANG"),
             TokenVariant::BangEqual => write!(f, "BANG_EQUAL"),
             TokenVariant::Equal => write!(f, "EQUAL"),
             TokenVariant::EqualEqual => write!(f, "EQUAL_EQUAL"),
+            TokenVariant::EqualGreater => write!(f, "EQUAL_GREATER"),
             TokenVariant::Greater => write!(f, "GREATER"),
             TokenVariant::GreaterEqual => write!(f, "GREATER_EQUAL"),
             TokenVariant::Less => write!(f, "LESS"),
@@ -72,9 +125,16 @@ impl fmt::Display for TokenVariant {
             TokenVariant::Identifier => write!(f, "IDENTIFIER"),
             TokenVariant::String(_) => write!(f, "STRING"),
             TokenVariant::Number(_) => write!(f, "NUMBER"),
+            TokenVariant::Integer(_) => write!(f, "INTEGER"),
+            TokenVariant::Function(_) => write!(f, "FUNCTION"),
+            TokenVariant::List(_) => write!(f, "LIST"),
+            TokenVariant::Method(_) => write!(f, "METHOD"),
             TokenVariant::Comment => write!(f, "COMMENT"),
+            TokenVariant::BlockComment => write!(f, "BLOCK_COMMENT"),
             TokenVariant::And => write!(f, "AND"),
+            TokenVariant::Break => write!(f, "BREAK"),
             TokenVariant::Class => write!(f, "CLASS"),
+            TokenVariant::Continue => write!(f, "CONTINUE"),
             TokenVariant::Else => write!(f, "ELSE"),
             TokenVariant::False => write!(f, "FALSE"),
             TokenVariant::Fun => write!(f, "FUN"),
@@ -99,6 +159,10 @@ pub struct Token {
     pub variant: TokenVariant,
     pub lexeme: String,
     pub line: u32,
+    /// Byte offset of the first character of the lexeme in the source
+    pub offset: usize,
+    /// 1-based column where the lexeme starts, counted in characters rather than bytes
+    pub column: u32,
 }
 
 impl fmt::Display for Token {
@@ -110,6 +174,9 @@ impl fmt::Display for Token {
         if let TokenVariant::Number(x) = self.variant {
             value = format!("{:?}", x);
         }
+        if let TokenVariant::Integer(x) = self.variant {
+            value = x.to_string();
+        }
         write!(f, "{} {} {}", self.variant, self.lexeme, value)
     }
 }
@@ -120,6 +187,7 @@ impl From<(&str, u32)> for Token {
             variant: match literal {
                 "=" => TokenVariant::Equal,
                 "==" => TokenVariant::EqualEqual,
+                "=>" => TokenVariant::EqualGreater,
                 "!" => TokenVariant::Bang,
                 "!=" => TokenVariant::BangEqual,
                 "<" => TokenVariant::Less,
@@ -130,16 +198,38 @@ impl From<(&str, u32)> for Token {
                 ")" => TokenVariant::RightParen,
                 "{" => TokenVariant::LeftBrace,
                 "}" => TokenVariant::RightBrace,
+                "[" => TokenVariant::LeftBracket,
+                "]" => TokenVariant::RightBracket,
                 "." => TokenVariant::Dot,
                 "," => TokenVariant::Comma,
                 ";" => TokenVariant::Semicolon,
+                "?" => TokenVariant::Question,
+                ":" => TokenVariant::Colon,
                 "+" => TokenVariant::Plus,
                 "-" => TokenVariant::Minus,
                 "*" => TokenVariant::Star,
                 "/" => TokenVariant::Slash,
+                "%" => TokenVariant::Percent,
+                "**" => TokenVariant::StarStar,
+                "~/" => TokenVariant::TildeSlash,
+                "&" => TokenVariant::Ampersand,
+                "|" => TokenVariant::Pipe,
+                "^" => TokenVariant::Caret,
+                "~" => TokenVariant::Tilde,
+                "<<" => TokenVariant::LessLess,
+                ">>" => TokenVariant::GreaterGreater,
+                "+=" => TokenVariant::PlusEqual,
+                "-=" => TokenVariant::MinusEqual,
+                "*=" => TokenVariant::StarEqual,
+                "/=" => TokenVariant::SlashEqual,
+                "++" => TokenVariant::PlusPlus,
+                "--" => TokenVariant::MinusMinus,
                 "//" => TokenVariant::Comment,
+                "/*" => TokenVariant::BlockComment,
                 "and" => TokenVariant::And,
+                "break" => TokenVariant::Break,
                 "class" => TokenVariant::Class,
+                "continue" => TokenVariant::Continue,
                 "else" => TokenVariant::Else,
                 "false" => TokenVariant::False,
                 "fun" => TokenVariant::Fun,
@@ -158,11 +248,13 @@ impl From<(&str, u32)> for Token {
                 s if s.starts_with("\"") && s.ends_with("\"") && s.len() > 1 => {
                     TokenVariant::String(literal.trim_matches('\"').to_owned())
                 }
-                s if s.parse::<f64>().is_ok() => TokenVariant::Number(s.parse().unwrap()),
+                s if is_number_literal(s) => number_literal(s).unwrap_or(TokenVariant::Identifier),
                 _ => TokenVariant::Identifier,
             },
             lexeme: literal.to_owned(),
-            line
+            line,
+            offset: 0,
+            column: 0,
         }
     }
 }
@@ -172,11 +264,34 @@ impl From<(TokenVariant, u32)> for Token {
         Self {
             variant,
             lexeme: "".to_owned(),
-            line
+            line,
+            offset: 0,
+            column: 0,
         }
     }
 }
 
+/// A value the way `print` shows it. `open` holds the lists being printed
+/// around this one, so that a list holding itself prints as `[...]`
+/// instead of forever.
+fn value_print(variant: &TokenVariant, open: &mut Vec<*const List>) -> String {
+    match variant {
+        TokenVariant::String(x) => x.clone(),
+        TokenVariant::Number(x) => format!("{}", x),
+        TokenVariant::Integer(x) => x.to_string(),
+        TokenVariant::Function(x) => x.to_string(),
+        TokenVariant::Method(x) => x.to_string(),
+        TokenVariant::List(x) if open.contains(&Rc::as_ptr(x)) => "[...]".to_owned(),
+        TokenVariant::List(x) => {
+            open.push(Rc::as_ptr(x));
+            let items: Vec<String> = x.items.borrow().iter().map(|item| value_print(item, open)).collect();
+            open.pop();
+            format!("[{}]", items.join(", "))
+        }
+        _ => variant.to_string().to_lowercase(),
+    }
+}
+
 impl Token {
     pub fn short_print(&self) -> String {
         if let TokenVariant::String(x) = &self.variant {
@@ -185,46 +300,73 @@ impl Token {
         if let TokenVariant::Number(x) = self.variant {
             return format!("{:?}", x);
         }
+        if let TokenVariant::Integer(x) = self.variant {
+            return x.to_string();
+        }
         self.lexeme.clone()
     }
 
     pub fn value_print(&self) -> String {
-        if let TokenVariant::String(x) = &self.variant {
-            return x.clone();
-        }
-        if let TokenVariant::Number(x) = self.variant {
-            return format!("{}", x);
-        }
-        self.variant.to_string().to_lowercase()
+        value_print(&self.variant, &mut Vec::new())
     }
 
     pub fn is_unary_operator(&self) -> bool {
-        match self.variant {
-            TokenVariant::Minus | TokenVariant::Bang => true,
-            _ => false,
-        }
+        matches!(self.variant, TokenVariant::Minus | TokenVariant::Bang | TokenVariant::Tilde)
     }
 
     pub fn is_binary_operator(&self) -> bool {
-        match self.variant {
+        matches!(
+            self.variant,
             TokenVariant::Plus
-            | TokenVariant::Minus
-            | TokenVariant::Star
-            | TokenVariant::Slash
-            | TokenVariant::EqualEqual
-            | TokenVariant::BangEqual
-            | TokenVariant::Less
-            | TokenVariant::LessEqual
-            | TokenVariant::Greater
-            | TokenVariant::GreaterEqual => true,
-            _ => false,
-        }
+                | TokenVariant::Minus
+                | TokenVariant::Star
+                | TokenVariant::Slash
+                | TokenVariant::Percent
+                | TokenVariant::StarStar
+                | TokenVariant::TildeSlash
+                | TokenVariant::Ampersand
+                | TokenVariant::Pipe
+                | TokenVariant::Caret
+                | TokenVariant::LessLess
+                | TokenVariant::GreaterGreater
+                | TokenVariant::EqualEqual
+                | TokenVariant::BangEqual
+                | TokenVariant::Less
+                | TokenVariant::LessEqual
+                | TokenVariant::Greater
+                | TokenVariant::GreaterEqual
+        )
+    }
+
+    /// `=` and the compound assignments such as `+=`.
+    pub fn is_assignment(&self) -> bool {
+        matches!(
+            self.variant,
+            TokenVariant::Equal
+                | TokenVariant::PlusEqual
+                | TokenVariant::MinusEqual
+                | TokenVariant::StarEqual
+                | TokenVariant::SlashEqual
+        )
+    }
+
+    pub fn is_increment(&self) -> bool {
+        matches!(self.variant, TokenVariant::PlusPlus | TokenVariant::MinusMinus)
     }
 
+    /// How tightly a binary operator binds, loosest first. `**` binds tighter
+    /// than the unary operators and groups to the right.
     pub fn get_precedence(&self) -> u32 {
         match self.variant {
-            TokenVariant::Plus | TokenVariant::Minus => 1,
-            TokenVariant::Slash | TokenVariant::Star => 2,
+            TokenVariant::EqualEqual | TokenVariant::BangEqual => 1,
+            TokenVariant::Less | TokenVariant::LessEqual | TokenVariant::Greater | TokenVariant::GreaterEqual => 2,
+            TokenVariant::Pipe => 3,
+            TokenVariant::Caret => 4,
+            TokenVariant::Ampersand => 5,
+            TokenVariant::LessLess | TokenVariant::GreaterGreater => 6,
+            TokenVariant::Plus | TokenVariant::Minus => 7,
+            TokenVariant::Slash | TokenVariant::Star | TokenVariant::Percent | TokenVariant::TildeSlash => 8,
+            TokenVariant::StarStar => 9,
             _ => 0
         }
     }
@@ -234,6 +376,8 @@ impl Token {
 enum LexicalErrorVariant {
     UnexpectedCharacter(char),
     UnterminatedString,
+    UnterminatedBlockComment,
+    InvalidNumber(String),
 }
 
 impl fmt::Display for LexicalErrorVariant {
@@ -241,6 +385,10 @@ impl fmt::Display for LexicalErrorVariant {
         match self {
             LexicalErrorVariant::UnexpectedCharacter(c) => write!(f, "Unexpected character: {c}"),
             LexicalErrorVariant::UnterminatedString => write!(f, "Unterminated string."),
+            LexicalErrorVariant::UnterminatedBlockComment => {
+                write!(f, "Unterminated block comment.")
+            }
+            LexicalErrorVariant::InvalidNumber(s) => write!(f, "Invalid number literal: {s}"),
         }
     }
 }
@@ -255,6 +403,14 @@ impl LexicalError {
     fn new(variant: LexicalErrorVariant, line: u32) -> Self {
         Self { variant, line }
     }
+
+    pub fn line(&self) -> u32 {
+        self.line
+    }
+
+    pub fn message(&self) -> String {
+        self.variant.to_string()
+    }
 }
 
 impl fmt::Display for LexicalError {
@@ -263,57 +419,201 @@ impl fmt::Display for LexicalError {
     }
 }
 
-fn process_char(buf: &mut String, c: Option<char>, current_line: u32) -> (Vec<Token>, Vec<LexicalErrorVariant>) {
+/// Approximates Unicode `XID_Start` (plus `_`) with what the standard library exposes.
+fn is_identifier_start(c: char) -> bool {
+    c == '_' || c.is_alphabetic()
+}
+
+/// Approximates Unicode `XID_Continue`: identifier starts, digits of any script,
+/// combining marks, connector punctuation and the zero-width joiners.
+fn is_identifier_continue(c: char) -> bool {
+    is_identifier_start(c)
+        || c.is_numeric()
+        || matches!(
+            c,
+            '\u{0300}'..='\u{036F}'
+                | '\u{1AB0}'..='\u{1AFF}'
+                | '\u{1DC0}'..='\u{1DFF}'
+                | '\u{20D0}'..='\u{20FF}'
+                | '\u{FE20}'..='\u{FE2F}'
+                | '\u{200C}'
+                | '\u{200D}'
+                | '\u{203F}'
+                | '\u{2040}'
+                | '\u{2054}'
+                | '\u{FE33}'
+                | '\u{FE34}'
+                | '\u{FE4D}'..='\u{FE4F}'
+                | '\u{FF3F}'
+        )
+}
+
+fn is_number_literal(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_ascii_digit())
+}
+
+fn radix_of(s: &str) -> u32 {
+    match s.get(..2) {
+        Some("0x" | "0X") => 16,
+        Some("0b" | "0B") => 2,
+        _ => 10,
+    }
+}
+
+/// Whether `c` can extend the numeric literal accumulated so far in `s`.
+/// Letters and underscores are always absorbed so that `123abc` is reported
+/// as one malformed literal instead of a number followed by an identifier.
+fn continues_number(s: &str, c: char) -> bool {
+    let last = s.chars().last();
+    if last == Some('.') {
+        return c.is_ascii_digit();
+    }
+    match c {
+        '.' => {
+            radix_of(s) == 10
+                && !s.contains(['.', 'e', 'E'])
+                && last.is_some_and(|x| x.is_ascii_digit())
+        }
+        '+' | '-' => radix_of(s) == 10 && matches!(last, Some('e' | 'E')),
+        c => is_identifier_continue(c),
+    }
+}
+
+/// Converts a complete numeric literal such as `1_000`, `0xFF`, `0b1010` or
+/// `1.5e-9` into its value.
+fn parse_number(s: &str) -> Result<f64, LexicalErrorVariant> {
+    let invalid = || LexicalErrorVariant::InvalidNumber(s.to_owned());
+    let radix = radix_of(s);
+    let body = if radix == 10 { s } else { &s[2..] };
+    let chars: Vec<char> = body.chars().collect();
+    // Underscores are only allowed between two digits
+    for (i, c) in chars.iter().enumerate() {
+        if *c == '_' {
+            let before = i.checked_sub(1).and_then(|j| chars.get(j));
+            let after = chars.get(i + 1);
+            if !before.is_some_and(|x| x.is_digit(radix)) || !after.is_some_and(|x| x.is_digit(radix)) {
+                return Err(invalid());
+            }
+        }
+    }
+    let digits: String = body.chars().filter(|c| *c != '_').collect();
+    if radix != 10 {
+        return u64::from_str_radix(&digits, radix)
+            .map(|n| n as f64)
+            .map_err(|_| invalid());
+    }
+    let (mantissa, exponent) = match digits.find(['e', 'E']) {
+        Some(i) => (&digits[..i], Some(&digits[i + 1..])),
+        None => (digits.as_str(), None),
+    };
+    let (whole, fraction) = match mantissa.split_once('.') {
+        Some((w, f)) => (w, Some(f)),
+        None => (mantissa, None),
+    };
+    let all_digits = |x: &str| !x.is_empty() && x.chars().all(|c| c.is_ascii_digit());
+    let valid = all_digits(whole)
+        && fraction.into_iter().all(all_digits)
+        && exponent
+            .into_iter()
+            .all(|e| all_digits(e.strip_prefix(['+', '-']).unwrap_or(e)));
+    match digits.parse::<f64>() {
+        Ok(n) if valid => Ok(n),
+        _ => Err(invalid()),
+    }
+}
+
+/// Converts an integer literal, a whole number with an `i` suffix such as
+/// `42i`, `1_000i` or `0xFFi`, into its value.
+fn parse_integer(s: &str) -> Result<i64, LexicalErrorVariant> {
+    let invalid = || LexicalErrorVariant::InvalidNumber(s.to_owned());
+    let body = s.strip_suffix('i').ok_or_else(invalid)?;
+    let radix = radix_of(body);
+    // A fraction or an exponent would make it a float
+    if radix == 10 && body.contains(['.', 'e', 'E']) {
+        return Err(invalid());
+    }
+    parse_number(body).map_err(|_| invalid())?;
+    let digits: String = body[if radix == 10 { 0 } else { 2 }..].chars().filter(|c| *c != '_').collect();
+    i64::from_str_radix(&digits, radix).map_err(|_| invalid())
+}
+
+/// The value of a complete numeric literal, an integer if it ends in `i`.
+fn number_literal(s: &str) -> Result<TokenVariant, LexicalErrorVariant> {
+    if s.ends_with('i') {
+        parse_integer(s).map(TokenVariant::Integer)
+    } else {
+        parse_number(s).map(TokenVariant::Number)
+    }
+}
+
+/// Feeds one character (or `None` at the end of the input) into the lexer.
+/// `offset` is the byte offset of `c`, so the buffer starts at `offset - buf.len()`.
+fn process_char(
+    buf: &mut String,
+    c: Option<char>,
+    current_line: u32,
+    offset: usize,
+) -> (Vec<Token>, Vec<LexicalErrorVariant>) {
     let mut tokens: Vec<Token> = Vec::new();
     let mut errors: Vec<LexicalErrorVariant> = Vec::new();
     let joined = format!("{}{}", buf.as_str(), c.unwrap_or('\0'));
     let try_token = Token::from((joined.as_str(), current_line));
+    let start = offset - buf.len();
+    let at = |token: Token, offset: usize| Token { offset, ..token };
     match try_token.variant {
         TokenVariant::Bang
         | TokenVariant::Equal
         | TokenVariant::Greater
         | TokenVariant::Less
-        | TokenVariant::Slash => {
-            buf.push(c.unwrap());
+        | TokenVariant::Slash
+        | TokenVariant::Star
+        | TokenVariant::Tilde
+        | TokenVariant::Plus
+        | TokenVariant::Minus => {
+            buf.extend(c);
         }
-        TokenVariant::Number(_) | TokenVariant::Identifier | TokenVariant::Eof => {
+        TokenVariant::Number(_) | TokenVariant::Integer(_) | TokenVariant::Identifier | TokenVariant::Eof => {
             match (buf.as_str(), c) {
-                (s, _) if matches!(s, "=" | "!" | "<" | ">" | "/") => {
-                    tokens.push(Token::from((s, current_line)));
+                (s, _) if matches!(s, "=" | "!" | "<" | ">" | "/" | "*" | "~" | "+" | "-") => {
+                    tokens.push(at(Token::from((s, current_line)), start));
                     buf.clear();
-                    let (mut t, mut e) = process_char(buf, c, current_line);
+                    let (mut t, mut e) = process_char(buf, c, current_line, offset);
                     tokens.append(&mut t);
                     errors.append(&mut e);
                 }
-                ("", Some(c)) if matches!(c, '\"' | '1'..='9') => {
+                ("", Some(c)) if matches!(c, '\"' | '0'..='9') => {
                     buf.push(c);
                 }
                 (s, Some(any_char)) if s.starts_with('\"') => {
                     buf.push(any_char);
                 }
-                (s, Some('.')) if s.parse::<u64>().is_ok() => {
-                    buf.push('.');
-                }
-                (s, Some(digit)) if s.parse::<f64>().is_ok() && digit.is_digit(10) => {
-                    buf.push(digit);
+                (s, Some(c)) if is_number_literal(s) && continues_number(s, c) => {
+                    buf.push(c);
                 }
-                (s, _) if s.parse::<f64>().is_ok() => {
-                    tokens.push(Token::from((s.trim_matches('.'), current_line)));
+                (s, _) if is_number_literal(s) => {
+                    let literal = s.strip_suffix('.').unwrap_or(s);
+                    match number_literal(literal) {
+                        Ok(_) => tokens.push(at(Token::from((literal, current_line)), start)),
+                        Err(e) => errors.push(e),
+                    }
                     if s.ends_with('.') {
-                        tokens.push(Token::from((".", current_line)));
+                        tokens.push(at(Token::from((".", current_line)), offset - 1));
                     }
                     buf.clear();
-                    let (mut t, mut e) = process_char(buf, c, current_line);
+                    let (mut t, mut e) = process_char(buf, c, current_line, offset);
                     tokens.append(&mut t);
                     errors.append(&mut e);
                 }
-                (_, Some(c)) if c.is_alphanumeric() || c == '_' => {
+                ("", Some(c)) if is_identifier_start(c) => {
+                    buf.push(c);
+                }
+                (s, Some(c)) if !s.is_empty() && is_identifier_continue(c) => {
                     buf.push(c);
                 }
                 (s, _) if !s.is_empty() && !s.starts_with('\"') => {
-                    tokens.push(Token::from((s, current_line)));
+                    tokens.push(at(Token::from((s, current_line)), start));
                     buf.clear();
-                    let (mut t, mut e) = process_char(buf, c, current_line);
+                    let (mut t, mut e) = process_char(buf, c, current_line, offset);
                     tokens.append(&mut t);
                     errors.append(&mut e);
                 }
@@ -329,46 +629,239 @@ fn process_char(buf: &mut String, c: Option<char>, current_line: u32) -> (Vec<To
                 (_, None) => {}
             }
         }
+        // A keyword so far, but `nil` may still become `nils`
+        _ if c.is_some_and(is_identifier_continue) && joined.starts_with(is_identifier_start) => {
+            buf.extend(c);
+        }
         _ => {
             buf.clear();
-            tokens.push(try_token);
+            tokens.push(at(try_token, start));
         }
     }
     (tokens, errors)
 }
 
-pub fn tokenize(file_contents: &String) -> (Vec<Token>, Option<Vec<LexicalError>>) {
+pub fn tokenize(file_contents: &str) -> (Vec<Token>, Option<Vec<LexicalError>>) {
     let mut tokens = Vec::new();
     let mut errors = Vec::new();
     let mut buf = String::new();
     let mut is_comment = false;
+    let mut block_comment_depth: u32 = 0;
+    let mut previous: Option<char> = None;
     let mut current_line = 1;
-    for c in file_contents
-        .chars()
-        .map(|x| Some(x))
-        .chain(iter::once(None))
+    let mut current_column = 0;
+    // A leading byte order mark is not part of the program, but offsets still count it
+    let start = if file_contents.starts_with('\u{feff}') { '\u{feff}'.len_utf8() } else { 0 };
+    for (offset, c) in file_contents[start..]
+        .char_indices()
+        .map(|(i, c)| (start + i, Some(c)))
+        .chain(iter::once((file_contents.len(), None)))
     {
-        match (c, is_comment) {
-            (Some('\n'), _) => {
-                is_comment = false;
-                current_line += 1;
+        current_column += 1;
+        if block_comment_depth > 0 {
+            // Block comments nest, so both delimiters are tracked until the outermost one closes
+            match (previous, c) {
+                (Some('*'), Some('/')) => {
+                    block_comment_depth -= 1;
+                    previous = None;
+                    continue;
+                }
+                (Some('/'), Some('*')) => {
+                    block_comment_depth += 1;
+                    previous = None;
+                    continue;
+                }
+                (_, Some('\n')) => {
+                    current_line += 1;
+                    current_column = 0;
+                }
+                (_, None) => errors.push(LexicalError::new(
+                    LexicalErrorVariant::UnterminatedBlockComment,
+                    current_line,
+                )),
+                _ => {}
             }
-            (_, false) => {
-                let (t, e) = process_char(&mut buf, c, current_line);
-                for token in t {
-                    if token.variant == TokenVariant::Comment {
-                        is_comment = true;
-                    } else {
-                        tokens.push(token);
+            previous = c;
+            continue;
+        }
+        if !is_comment {
+            let (t, e) = process_char(&mut buf, c, current_line, offset);
+            for mut token in t {
+                token.column = column_of(file_contents, token.offset, offset, current_column);
+                match token.variant {
+                    TokenVariant::Comment => is_comment = true,
+                    TokenVariant::BlockComment => {
+                        block_comment_depth = 1;
+                        previous = None;
                     }
+                    _ => tokens.push(token),
                 }
-                for error in e {
-                    errors.push(LexicalError::new(error, current_line));
-                }
             }
-            _ => {}
+            for error in e {
+                errors.push(LexicalError::new(error, current_line));
+            }
+        }
+        if c == Some('\n') {
+            is_comment = false;
+            current_line += 1;
+            current_column = 0;
+        }
+    }
+    let mut eof = Token::from((TokenVariant::Eof, current_line));
+    eof.offset = file_contents.len();
+    eof.column = current_column;
+    tokens.push(eof);
+    (tokens, (!errors.is_empty()).then_some(errors))
+}
+
+/// Column of the token starting at byte `start`, given that the character at
+/// byte `offset` sits in column `column` of the current line.
+fn column_of(file_contents: &str, start: usize, offset: usize, column: u32) -> u32 {
+    let between = &file_contents[start..offset];
+    if between.contains('\n') {
+        // Only multi-line strings start on an earlier line than the one being scanned
+        let line_start = file_contents[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line = &file_contents[line_start..start];
+        line.trim_start_matches('\u{feff}').chars().count() as u32 + 1
+    } else {
+        column - between.chars().count() as u32
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TriviaKind {
+    Whitespace,
+    Newline,
+    LineComment,
+    BlockComment,
+    ByteOrderMark,
+    /// Source text the lexer rejected, kept so that nothing is lost
+    Skipped,
+}
+
+impl fmt::Display for TriviaKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TriviaKind::Whitespace => write!(f, "WHITESPACE"),
+            TriviaKind::Newline => write!(f, "NEWLINE"),
+            TriviaKind::LineComment => write!(f, "LINE_COMMENT"),
+            TriviaKind::BlockComment => write!(f, "BLOCK_COMMENT"),
+            TriviaKind::ByteOrderMark => write!(f, "BYTE_ORDER_MARK"),
+            TriviaKind::Skipped => write!(f, "SKIPPED"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Trivia {
+    pub kind: TriviaKind,
+    pub text: String,
+}
+
+/// A token together with the whitespace and comments around it.
+/// Trailing trivia runs up to the end of the token's line, everything after
+/// that belongs to the next token.
+#[derive(Debug, PartialEq, Clone)]
+pub struct LosslessToken {
+    pub token: Token,
+    pub leading: Vec<Trivia>,
+    pub trailing: Vec<Trivia>,
+}
+
+impl fmt::Display for LosslessToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for trivia in self.leading.iter() {
+            write!(f, "{}", trivia.text)?;
+        }
+        write!(f, "{}", self.token.lexeme)?;
+        for trivia in self.trailing.iter() {
+            write!(f, "{}", trivia.text)?;
+        }
+        Ok(())
+    }
+}
+
+fn split_trivia(text: &str) -> Vec<Trivia> {
+    let mut trivia: Vec<Trivia> = Vec::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let (kind, len) = if c == '\u{feff}' {
+            (TriviaKind::ByteOrderMark, c.len_utf8())
+        } else if rest.starts_with("\r\n") {
+            (TriviaKind::Newline, "\r\n".len())
+        } else if c == '\n' {
+            (TriviaKind::Newline, 1)
+        } else if c.is_whitespace() {
+            let end = rest
+                .char_indices()
+                .find(|(i, x)| !x.is_whitespace() || *x == '\n' || rest[*i..].starts_with("\r\n"))
+                .map_or(rest.len(), |(i, _)| i);
+            (TriviaKind::Whitespace, end)
+        } else if rest.starts_with("//") {
+            let end = rest.find('\n').unwrap_or(rest.len());
+            (TriviaKind::LineComment, rest[..end].strip_suffix('\r').map_or(end, str::len))
+        } else if rest.starts_with("/*") {
+            (TriviaKind::BlockComment, block_comment_len(rest))
+        } else {
+            let end = rest
+                .char_indices()
+                .find(|(i, x)| {
+                    x.is_whitespace() || rest[*i..].starts_with("//") || rest[*i..].starts_with("/*")
+                })
+                .map_or(rest.len(), |(i, _)| i);
+            (TriviaKind::Skipped, end)
+        };
+        let len = len.max(c.len_utf8());
+        trivia.push(Trivia {
+            kind,
+            text: rest[..len].to_owned(),
+        });
+        rest = &rest[len..];
+    }
+    trivia
+}
+
+fn block_comment_len(text: &str) -> usize {
+    let mut depth = 0;
+    let mut i = 0;
+    while i < text.len() {
+        if text[i..].starts_with("/*") {
+            depth += 1;
+            i += 2;
+        } else if text[i..].starts_with("*/") {
+            depth -= 1;
+            i += 2;
+            if depth == 0 {
+                return i;
+            }
+        } else {
+            i += text[i..].chars().next().map_or(1, char::len_utf8);
+        }
+    }
+    text.len()
+}
+
+/// Like `tokenize()`, but every token keeps the source text around it, so that
+/// concatenating the tokens reproduces the input byte for byte.
+pub fn tokenize_lossless(file_contents: &str) -> (Vec<LosslessToken>, Option<Vec<LexicalError>>) {
+    let (tokens, errors) = tokenize(file_contents);
+    let mut lossless: Vec<LosslessToken> = Vec::new();
+    let mut end = 0;
+    for token in tokens {
+        let mut leading = split_trivia(&file_contents[end..token.offset]);
+        if let Some(previous) = lossless.last_mut() {
+            let newline = leading
+                .iter()
+                .position(|t| t.kind == TriviaKind::Newline)
+                .unwrap_or(leading.len());
+            previous.trailing = leading.drain(..newline).collect();
         }
+        end = token.offset + token.lexeme.len();
+        lossless.push(LosslessToken {
+            token,
+            leading,
+            trailing: Vec::new(),
+        });
     }
-    tokens.push(Token::from((TokenVariant::Eof, current_line)));
-    (tokens, (!errors.is_empty()).then(|| errors))
+    (lossless, errors)
 }
@@ -0,0 +1,331 @@
+use core::fmt;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use crate::json::Json;
+use crate::lexer::{LosslessToken, Token, TokenVariant, TriviaKind};
+use crate::parser::{self, Tree, TreeNode};
+
+const ALLOW_DIRECTIVE: &str = "lox-lint: allow(";
+
+#[derive(Debug)]
+pub struct Warning {
+    rule: &'static str,
+    line: u32,
+    message: String,
+}
+
+impl Warning {
+    pub fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("rule", self.rule.into()),
+            ("line", self.line.into()),
+            ("message", self.message.clone().into()),
+        ])
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[line {}] Warning: {} ({})", self.line, self.message, self.rule)
+    }
+}
+
+/// A single check. Rules only look at the tree; suppression and selection
+/// are handled by `lint()`.
+pub trait Rule {
+    fn name(&self) -> &'static str;
+    fn check(&self, node: &TreeNode, warnings: &mut Vec<Warning>);
+}
+
+/// `x == x`, `a < a` and friends always give the same answer.
+struct SelfComparison;
+
+impl Rule for SelfComparison {
+    fn name(&self) -> &'static str {
+        "self-comparison"
+    }
+
+    fn check(&self, node: &TreeNode, warnings: &mut Vec<Warning>) {
+        let is_comparison = matches!(
+            node.value.variant,
+            TokenVariant::EqualEqual
+                | TokenVariant::BangEqual
+                | TokenVariant::Less
+                | TokenVariant::LessEqual
+                | TokenVariant::Greater
+                | TokenVariant::GreaterEqual
+        );
+        if let (true, Some(left), Some(right)) = (is_comparison, &node.left, &node.right) {
+            if same_expression(left, right) {
+                warnings.push(Warning {
+                    rule: self.name(),
+                    line: node.value.line,
+                    message: format!("Comparing an expression with itself using '{}'.", node.value.lexeme),
+                });
+            }
+        }
+    }
+}
+
+/// Whether `a` and `b` always have the same value: they are written the same
+/// way, ignoring where the tokens are and any parentheses, and neither could
+/// give a different value the second time it runs.
+fn same_expression(a: &Rc<RefCell<TreeNode>>, b: &Rc<RefCell<TreeNode>>) -> bool {
+    !may_differ(a) && !may_differ(b) && same_structure(a, b)
+}
+
+/// Whether `node` calls something, changes a variable or builds a new list
+/// or function, any of which can make two runs of it differ.
+fn may_differ(node: &Rc<RefCell<TreeNode>>) -> bool {
+    let node = node.borrow();
+    match node.value.variant {
+        TokenVariant::LeftParen if node.left.is_some() => true,
+        TokenVariant::LeftBracket if node.left.is_none() => true,
+        TokenVariant::Equal
+        | TokenVariant::PlusEqual
+        | TokenVariant::MinusEqual
+        | TokenVariant::StarEqual
+        | TokenVariant::SlashEqual
+        | TokenVariant::PlusPlus
+        | TokenVariant::MinusMinus => true,
+        _ if is_function(&node) => true,
+        _ => [&node.left, &node.right].into_iter().flatten().any(may_differ),
+    }
+}
+
+fn same_structure(a: &Rc<RefCell<TreeNode>>, b: &Rc<RefCell<TreeNode>>) -> bool {
+    let (a, b) = (a.borrow(), b.borrow());
+    let same_child = |x: &Option<Rc<RefCell<TreeNode>>>, y: &Option<Rc<RefCell<TreeNode>>>| match (x, y) {
+        (Some(x), Some(y)) => same_structure(x, y),
+        (None, None) => true,
+        _ => false,
+    };
+    a.value.variant == b.value.variant
+        && a.value.lexeme == b.value.lexeme
+        && same_child(&a.left, &b.left)
+        && same_child(&a.right, &b.right)
+}
+
+/// `a = a` changes nothing.
+struct SelfAssignment;
+
+impl Rule for SelfAssignment {
+    fn name(&self) -> &'static str {
+        "self-assignment"
+    }
+
+    fn check(&self, node: &TreeNode, warnings: &mut Vec<Warning>) {
+        if let (TokenVariant::Equal, Some(target), Some(value)) = (&node.value.variant, &node.left, &node.right) {
+            if same_expression(target, value) {
+                warnings.push(Warning {
+                    rule: self.name(),
+                    line: node.value.line,
+                    message: "Assigning an expression to itself.".to_owned(),
+                });
+            }
+        }
+    }
+}
+
+/// A parameter the body never mentions. Names starting with `_` are
+/// expected to go unused.
+struct UnusedParameter;
+
+impl Rule for UnusedParameter {
+    fn name(&self) -> &'static str {
+        "unused-parameter"
+    }
+
+    fn check(&self, node: &TreeNode, warnings: &mut Vec<Warning>) {
+        if !is_function(node) {
+            return;
+        }
+        for parameter in parser::list(&node.left) {
+            let parameter = &parameter.borrow().value;
+            if parameter.lexeme.starts_with('_') || node.right.as_ref().is_some_and(|body| uses(body, &parameter.lexeme)) {
+                continue;
+            }
+            warnings.push(Warning {
+                rule: self.name(),
+                line: parameter.line,
+                message: format!("Parameter '{}' is never used.", parameter.lexeme),
+            });
+        }
+    }
+}
+
+/// A parameter with the same name as one of a function around it, which
+/// it hides from the body.
+struct ShadowedParameter;
+
+impl Rule for ShadowedParameter {
+    fn name(&self) -> &'static str {
+        "shadowed-parameter"
+    }
+
+    fn check(&self, node: &TreeNode, warnings: &mut Vec<Warning>) {
+        if !is_function(node) {
+            return;
+        }
+        for parameter in parser::list(&node.left) {
+            let name = &parameter.borrow().value.lexeme;
+            let mut shadows = Vec::new();
+            if let Some(body) = &node.right {
+                redeclarations(body, name, &mut shadows);
+            }
+            for shadow in shadows {
+                warnings.push(Warning {
+                    rule: self.name(),
+                    line: shadow.line,
+                    message: format!("Parameter '{}' shadows a parameter of an enclosing function.", name),
+                });
+            }
+        }
+    }
+}
+
+/// `true ? a : b` always takes the same branch.
+struct ConstantCondition;
+
+impl Rule for ConstantCondition {
+    fn name(&self) -> &'static str {
+        "constant-condition"
+    }
+
+    fn check(&self, node: &TreeNode, warnings: &mut Vec<Warning>) {
+        if let (TokenVariant::Question, Some(condition)) = (&node.value.variant, &node.left) {
+            if is_constant(condition) {
+                warnings.push(Warning {
+                    rule: self.name(),
+                    line: node.value.line,
+                    message: "Condition of '?:' is constant.".to_owned(),
+                });
+            }
+        }
+    }
+}
+
+fn is_function(node: &TreeNode) -> bool {
+    matches!(node.value.variant, TokenVariant::Fun | TokenVariant::EqualGreater)
+}
+
+fn declares(function: &TreeNode, name: &str) -> Option<Token> {
+    parser::list(&function.left)
+        .into_iter()
+        .map(|p| p.borrow().value.clone())
+        .find(|p| p.lexeme == name)
+}
+
+/// Whether `node` reads or assigns the variable `name`, leaving out functions
+/// whose own parameter of that name hides it.
+fn uses(node: &Rc<RefCell<TreeNode>>, name: &str) -> bool {
+    let node = node.borrow();
+    let child_uses = |child: &Option<Rc<RefCell<TreeNode>>>| child.as_ref().is_some_and(|c| uses(c, name));
+    match node.value.variant {
+        TokenVariant::Identifier => node.value.lexeme == name,
+        _ if is_function(&node) => declares(&node, name).is_none() && child_uses(&node.right),
+        // The right of `a.b` is the name of a property, not a variable
+        TokenVariant::Dot => child_uses(&node.left),
+        _ => child_uses(&node.left) || child_uses(&node.right),
+    }
+}
+
+/// Parameters called `name` of the outermost functions under `node` that
+/// declare one.
+fn redeclarations(node: &Rc<RefCell<TreeNode>>, name: &str, found: &mut Vec<Token>) {
+    let node = node.borrow();
+    if is_function(&node) {
+        if let Some(parameter) = declares(&node, name) {
+            found.push(parameter);
+            return;
+        }
+        if let Some(body) = &node.right {
+            redeclarations(body, name, found);
+        }
+        return;
+    }
+    for child in [&node.left, &node.right].into_iter().flatten() {
+        redeclarations(child, name, found);
+    }
+}
+
+/// Whether `node` always has the same value: it reads no variable and calls
+/// nothing. A function is a new value each time, but always a truthy one.
+fn is_constant(node: &Rc<RefCell<TreeNode>>) -> bool {
+    let node = node.borrow();
+    match node.value.variant {
+        TokenVariant::Identifier => false,
+        _ if is_function(&node) => true,
+        TokenVariant::LeftParen if node.left.is_some() => false,
+        _ => [&node.left, &node.right].into_iter().flatten().all(is_constant),
+    }
+}
+
+pub fn rules() -> Vec<Box<dyn Rule>> {
+    vec![
+        Box::new(SelfComparison),
+        Box::new(SelfAssignment),
+        Box::new(UnusedParameter),
+        Box::new(ShadowedParameter),
+        Box::new(ConstantCondition),
+    ]
+}
+
+fn walk(node: &Rc<RefCell<TreeNode>>, rules: &[&dyn Rule], warnings: &mut Vec<Warning>) {
+    let node = node.borrow();
+    for rule in rules.iter() {
+        rule.check(&node, warnings);
+    }
+    for child in [&node.left, &node.right].into_iter().flatten() {
+        walk(child, rules, warnings);
+    }
+}
+
+/// Rules allowed per line by `// lox-lint: allow(rule, ...)` comments. A comment
+/// covers its own line and the line after it.
+fn suppressions(tokens: &[LosslessToken]) -> HashMap<u32, HashSet<String>> {
+    let mut allowed: HashMap<u32, HashSet<String>> = HashMap::new();
+    let mut line = 1;
+    for t in tokens.iter() {
+        let trivia = t.leading.iter().map(|x| (x.kind, x.text.as_str()));
+        let lexeme = [(TriviaKind::Skipped, t.token.lexeme.as_str())];
+        let trailing = t.trailing.iter().map(|x| (x.kind, x.text.as_str()));
+        for (kind, text) in trivia.chain(lexeme).chain(trailing) {
+            if kind == TriviaKind::LineComment {
+                let names = text
+                    .split_once(ALLOW_DIRECTIVE)
+                    .and_then(|(_, rest)| rest.split_once(')'))
+                    .map_or("", |(names, _)| names);
+                for name in names.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+                    for covered in [line, line + 1] {
+                        allowed.entry(covered).or_default().insert(name.to_owned());
+                    }
+                }
+            }
+            line += text.matches('\n').count() as u32;
+        }
+    }
+    allowed
+}
+
+/// Runs the enabled rules over a parsed file. `tokens` come from
+/// `lexer::tokenize_lossless()` on the same source and are only used for
+/// suppression comments.
+pub fn lint(ast: &Tree, tokens: &[LosslessToken], enabled: &HashSet<&str>) -> Vec<Warning> {
+    let rules = rules();
+    let active: Vec<&dyn Rule> = rules
+        .iter()
+        .map(|r| r.as_ref())
+        .filter(|r| enabled.contains(r.name()))
+        .collect();
+    let mut warnings = Vec::new();
+    if let Some(root) = &ast.root {
+        walk(root, &active, &mut warnings);
+    }
+    let allowed = suppressions(tokens);
+    warnings.retain(|w| !allowed.get(&w.line).is_some_and(|names| names.contains(w.rule)));
+    warnings.sort_by_key(|w| w.line);
+    warnings
+}
@@ -0,0 +1,695 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use crate::cst::{self, Element, Node, NodeKind};
+use crate::evaluator;
+use crate::json::Json;
+use crate::lexer::{self, LosslessToken, TokenVariant, Trivia, TriviaKind};
+use crate::parser;
+
+const TOKEN_TYPES: [&str; 6] = ["keyword", "number", "string", "operator", "variable", "comment"];
+const SYMBOL_FUNCTION: u32 = 12;
+const SYMBOL_VARIABLE: u32 = 13;
+
+/// Reads one `Content-Length` framed message. `None` means the client hung up.
+pub fn read_message(input: &mut impl BufRead) -> io::Result<Option<Json>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let mut body = vec![0; length.unwrap_or(0)];
+    input.read_exact(&mut body)?;
+    let body = String::from_utf8_lossy(&body);
+    Json::parse(&body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+}
+
+pub fn write_message(message: &Json) {
+    let body = message.to_string();
+    let mut stdout = io::stdout().lock();
+    let _ = write!(stdout, "Content-Length: {}\r\n\r\n{}", body.len(), body);
+    let _ = stdout.flush();
+}
+
+/// Maps between byte offsets and LSP positions, whose columns count UTF-16 code units.
+pub struct LineIndex<'a> {
+    text: &'a str,
+    starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    pub fn new(text: &'a str) -> Self {
+        let mut starts = vec![0];
+        starts.extend(text.match_indices('\n').map(|(i, _)| i + 1));
+        Self { text, starts }
+    }
+
+    pub fn position(&self, offset: usize) -> (u32, u32) {
+        let line = self.starts.partition_point(|s| *s <= offset) - 1;
+        let prefix = &self.text[self.starts[line]..offset];
+        (line as u32, prefix.encode_utf16().count() as u32)
+    }
+
+    pub fn offset(&self, line: u32, character: u32) -> usize {
+        let Some(start) = self.starts.get(line as usize).copied() else {
+            return self.text.len();
+        };
+        let mut units = 0;
+        for (i, c) in self.text[start..].char_indices() {
+            if units >= character || c == '\n' {
+                return start + i;
+            }
+            units += c.len_utf16() as u32;
+        }
+        self.text.len()
+    }
+
+    pub fn line_length(&self, line: u32) -> u32 {
+        let start = self.starts.get(line as usize).copied().unwrap_or(self.text.len());
+        let end = self.text[start..].find('\n').map_or(self.text.len(), |i| start + i);
+        self.text[start..end].trim_end_matches('\r').encode_utf16().count() as u32
+    }
+}
+
+fn position(line: u32, character: u32) -> Json {
+    Json::object(vec![("line", line.into()), ("character", character.into())])
+}
+
+fn range(index: &LineIndex, start: usize, end: usize) -> Json {
+    let (start_line, start_character) = index.position(start);
+    let (end_line, end_character) = index.position(end);
+    Json::object(vec![
+        ("start", position(start_line, start_character)),
+        ("end", position(end_line, end_character)),
+    ])
+}
+
+fn line_range(index: &LineIndex, line: u32) -> Json {
+    let line = line.saturating_sub(1);
+    Json::object(vec![
+        ("start", position(line, 0)),
+        ("end", position(line, index.line_length(line))),
+    ])
+}
+
+const ERROR: u32 = 1;
+const WARNING: u32 = 2;
+
+fn diagnostic(range: Json, severity: u32, message: String) -> Json {
+    Json::object(vec![
+        ("range", range),
+        ("severity", severity.into()),
+        ("source", "lox".into()),
+        ("message", message.into()),
+    ])
+}
+
+/// Errors from every stage that can run, reported per line like the command
+/// line does, then a warning for each name no function around it declares.
+fn diagnostics(text: &str) -> Vec<Json> {
+    let index = LineIndex::new(text);
+    let (tokens, errors) = lexer::tokenize(text);
+    if let Some(errors) = errors {
+        return errors
+            .iter()
+            .map(|e| diagnostic(line_range(&index, e.line()), ERROR, e.message()))
+            .collect();
+    }
+    if let Err(errors) = parser::parse(&tokens) {
+        return errors
+            .iter()
+            .map(|e| diagnostic(line_range(&index, e.line().unwrap_or(1)), ERROR, e.message()))
+            .collect();
+    }
+    let (tokens, _) = lexer::tokenize_lossless(text);
+    let Ok(tree) = cst::parse(tokens) else {
+        return Vec::new();
+    };
+    let mut occurrences = Vec::new();
+    resolve(&tree, &mut Vec::new(), &mut occurrences);
+    occurrences
+        .iter()
+        .filter(|o| o.declaration.is_none())
+        .map(|o| {
+            let range = range(&index, o.offset, o.offset + o.name.len());
+            diagnostic(range, WARNING, format!("Undefined variable '{}'.", o.name))
+        })
+        .collect()
+}
+
+fn token_type(variant: &TokenVariant) -> Option<usize> {
+    match variant {
+        TokenVariant::Number(_) | TokenVariant::Integer(_) => Some(1),
+        TokenVariant::String(_) => Some(2),
+        TokenVariant::Identifier => Some(4),
+        TokenVariant::Eof
+        | TokenVariant::LeftParen
+        | TokenVariant::RightParen
+        | TokenVariant::LeftBrace
+        | TokenVariant::RightBrace
+        | TokenVariant::Comma
+        | TokenVariant::Dot
+        | TokenVariant::Semicolon
+        | TokenVariant::Comment
+        | TokenVariant::BlockComment
+        | TokenVariant::LeftBracket
+        | TokenVariant::RightBracket
+        | TokenVariant::Function(_)
+        | TokenVariant::List(_)
+        | TokenVariant::Method(_) => None,
+        TokenVariant::Equal
+        | TokenVariant::EqualEqual
+        | TokenVariant::EqualGreater
+        | TokenVariant::Bang
+        | TokenVariant::BangEqual
+        | TokenVariant::Less
+        | TokenVariant::LessEqual
+        | TokenVariant::Greater
+        | TokenVariant::GreaterEqual
+        | TokenVariant::Plus
+        | TokenVariant::Minus
+        | TokenVariant::Star
+        | TokenVariant::Slash
+        | TokenVariant::Percent
+        | TokenVariant::StarStar
+        | TokenVariant::TildeSlash
+        | TokenVariant::Ampersand
+        | TokenVariant::Pipe
+        | TokenVariant::Caret
+        | TokenVariant::Tilde
+        | TokenVariant::LessLess
+        | TokenVariant::GreaterGreater
+        | TokenVariant::PlusEqual
+        | TokenVariant::MinusEqual
+        | TokenVariant::StarEqual
+        | TokenVariant::SlashEqual
+        | TokenVariant::PlusPlus
+        | TokenVariant::MinusMinus
+        | TokenVariant::Question
+        | TokenVariant::Colon => Some(3),
+        _ => Some(0),
+    }
+}
+
+/// Encodes highlighting spans the way `textDocument/semanticTokens` expects:
+/// each span relative to the previous one, split at line breaks.
+fn semantic_tokens(text: &str) -> Vec<Json> {
+    let index = LineIndex::new(text);
+    let mut spans: Vec<(usize, usize, usize)> = Vec::new();
+    let (tokens, _) = lexer::tokenize_lossless(text);
+    let mut push_comments = |trivia: &[Trivia], mut offset: usize| {
+        for x in trivia.iter() {
+            if matches!(x.kind, TriviaKind::LineComment | TriviaKind::BlockComment) {
+                spans.push((offset, x.text.len(), 5));
+            }
+            offset += x.text.len();
+        }
+    };
+    for t in tokens.iter() {
+        let leading: usize = t.leading.iter().map(|x| x.text.len()).sum();
+        push_comments(&t.leading, t.token.offset - leading);
+        push_comments(&t.trailing, t.token.offset + t.token.lexeme.len());
+    }
+    for t in tokens.iter() {
+        if let Some(kind) = token_type(&t.token.variant) {
+            spans.push((t.token.offset, t.token.lexeme.len(), kind));
+        }
+    }
+    spans.sort();
+    let mut data = Vec::new();
+    let (mut last_line, mut last_character) = (0, 0);
+    for (start, len, kind) in spans {
+        let mut piece_start = start;
+        for piece in text[start..start + len].split('\n') {
+            let (line, character) = index.position(piece_start);
+            let width = piece.trim_end_matches('\r').encode_utf16().count() as u32;
+            if width > 0 {
+                let delta = if line == last_line { character - last_character } else { character };
+                data.extend([line - last_line, delta, width, kind as u32, 0].map(Json::from));
+                (last_line, last_character) = (line, character);
+            }
+            piece_start += piece.len() + 1;
+        }
+    }
+    data
+}
+
+/// The innermost node that directly owns the token starting at `offset`.
+fn node_owning(node: &Node, offset: usize) -> Option<&Node> {
+    for child in node.children.iter() {
+        match child {
+            Element::Node(n) => {
+                if let Some(found) = node_owning(n, offset) {
+                    return Some(found);
+                }
+            }
+            Element::Token(t) if t.token.offset == offset && !t.token.lexeme.is_empty() => {
+                return Some(node);
+            }
+            Element::Token(_) => {}
+        }
+    }
+    None
+}
+
+/// The kind of value an expression produces, as far as it can be known without running it.
+fn value_kind(element: &Element) -> Option<&'static str> {
+    let node = match element {
+        Element::Node(n) => n,
+        Element::Token(t) => {
+            return match t.token.variant {
+                TokenVariant::Number(_) => Some("number"),
+                TokenVariant::Integer(_) => Some("integer"),
+                TokenVariant::String(_) => Some("string"),
+                TokenVariant::True | TokenVariant::False => Some("boolean"),
+                TokenVariant::Nil => Some("nil"),
+                _ => None,
+            }
+        }
+    };
+    let operator = |i: usize| match node.children.get(i) {
+        Some(Element::Token(t)) => Some(t.token.variant.clone()),
+        _ => None,
+    };
+    match node.kind {
+        NodeKind::Literal | NodeKind::Root => value_kind(node.children.first()?),
+        NodeKind::Grouping => value_kind(node.children.get(1)?),
+        NodeKind::Unary => match operator(0)? {
+            TokenVariant::Minus if value_kind(node.children.get(1)?) == Some("integer") => Some("integer"),
+            TokenVariant::Minus => Some("number"),
+            TokenVariant::Tilde => Some("integer"),
+            TokenVariant::Bang => Some("boolean"),
+            _ => None,
+        },
+        NodeKind::Binary => match operator(1)? {
+            TokenVariant::Plus => {
+                match (value_kind(&node.children[0]), value_kind(&node.children[2])) {
+                    (Some("integer"), Some("integer")) => Some("integer"),
+                    (Some("number" | "integer"), Some("number" | "integer")) => Some("number"),
+                    (Some("string"), Some("string")) => Some("string"),
+                    _ => None,
+                }
+            }
+            TokenVariant::Ampersand
+            | TokenVariant::Pipe
+            | TokenVariant::Caret
+            | TokenVariant::LessLess
+            | TokenVariant::GreaterGreater => Some("integer"),
+            TokenVariant::Minus
+            | TokenVariant::Star
+            | TokenVariant::Slash
+            | TokenVariant::Percent
+            | TokenVariant::StarStar
+            | TokenVariant::TildeSlash => {
+                match (value_kind(&node.children[0]), value_kind(&node.children[2])) {
+                    (Some("integer"), Some("integer")) => Some("integer"),
+                    _ => Some("number"),
+                }
+            }
+            _ => Some("boolean"),
+        },
+        // Variables have no known kind until they can be declared
+        NodeKind::Assignment
+        | NodeKind::Postfix
+        | NodeKind::Call
+        | NodeKind::Index
+        | NodeKind::Property
+        | NodeKind::Error => None,
+        NodeKind::Function => Some("function"),
+        NodeKind::List => Some("list"),
+        NodeKind::Conditional => match (value_kind(node.children.get(2)?), value_kind(node.children.get(4)?)) {
+            (Some(a), Some(b)) if a == b => Some(a),
+            _ => None,
+        },
+        NodeKind::Sequence => value_kind(node.children.get(2)?),
+    }
+}
+
+fn token_at(tokens: &[LosslessToken], offset: usize) -> Option<&LosslessToken> {
+    tokens.iter().find(|t| {
+        !t.token.lexeme.is_empty()
+            && t.token.offset <= offset
+            && offset <= t.token.offset + t.token.lexeme.len()
+    })
+}
+
+fn hover(text: &str, line: u32, character: u32) -> Json {
+    let index = LineIndex::new(text);
+    let offset = index.offset(line, character);
+    let (tokens, _) = lexer::tokenize_lossless(text);
+    let Some(t) = token_at(&tokens, offset) else {
+        return Json::Null;
+    };
+    let start = t.token.offset;
+    let end = start + t.token.lexeme.len();
+    // Nesting too deep to walk has no kind to show
+    let Ok(tree) = cst::parse(tokens.clone()) else {
+        return Json::Null;
+    };
+    let kind = match node_owning(&tree, start) {
+        Some(node) => value_kind(&Element::Node(node.clone())),
+        None => None,
+    };
+    match kind {
+        Some(kind) => Json::object(vec![
+            (
+                "contents",
+                Json::object(vec![("kind", "markdown".into()), ("value", format!("`{kind}`").into())]),
+            ),
+            ("range", range(&index, start, end)),
+        ]),
+        None => Json::Null,
+    }
+}
+
+/// An identifier in a document and the parameter it names: its own offset
+/// for a parameter, the innermost enclosing parameter with the same name for
+/// a use, and `None` for a name no function around it declares.
+struct Occurrence<'a> {
+    name: &'a str,
+    offset: usize,
+    declaration: Option<usize>,
+}
+
+/// The parameters a function declares, with their offsets.
+fn parameters(function: &Node) -> Vec<(&str, usize)> {
+    function
+        .children
+        .iter()
+        .take_while(|c| !matches!(c, Element::Token(t) if t.token.variant == TokenVariant::RightParen))
+        .filter_map(|c| match c {
+            Element::Token(t) if t.token.variant == TokenVariant::Identifier => Some((t.token.lexeme.as_str(), t.token.offset)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Collects every variable name under `node`, resolving each use the way the
+/// evaluator does: to the parameters of the functions it is written in,
+/// innermost first. Names of properties are not variables and are skipped.
+fn resolve<'a>(node: &'a Node, scopes: &mut Vec<Vec<(&'a str, usize)>>, occurrences: &mut Vec<Occurrence<'a>>) {
+    let function = node.kind == NodeKind::Function;
+    if function {
+        let declared = parameters(node);
+        for (name, offset) in declared.iter() {
+            occurrences.push(Occurrence { name, offset: *offset, declaration: Some(*offset) });
+        }
+        scopes.push(declared);
+    }
+    for (i, child) in node.children.iter().enumerate() {
+        match child {
+            Element::Node(n) => resolve(n, scopes, occurrences),
+            Element::Token(t) if t.token.variant == TokenVariant::Identifier => {
+                if function || (node.kind == NodeKind::Property && i > 0) {
+                    continue;
+                }
+                let name = t.token.lexeme.as_str();
+                let declaration = scopes
+                    .iter()
+                    .rev()
+                    .find_map(|scope| scope.iter().find(|(n, _)| *n == name).map(|(_, offset)| *offset));
+                occurrences.push(Occurrence { name, offset: t.token.offset, declaration });
+            }
+            Element::Token(_) => {}
+        }
+    }
+    if function {
+        scopes.pop();
+    }
+}
+
+/// The variable names in `tree` and the index of the one under `offset`.
+fn occurrences(tree: &Node, offset: usize) -> (Vec<Occurrence<'_>>, Option<usize>) {
+    let mut occurrences = Vec::new();
+    resolve(tree, &mut Vec::new(), &mut occurrences);
+    let target = occurrences
+        .iter()
+        .position(|o| o.offset <= offset && offset <= o.offset + o.name.len());
+    (occurrences, target)
+}
+
+fn location(uri: &str, index: &LineIndex, occurrence: &Occurrence) -> Json {
+    Json::object(vec![
+        ("uri", uri.into()),
+        ("range", range(index, occurrence.offset, occurrence.offset + occurrence.name.len())),
+    ])
+}
+
+/// The parameter the identifier under the cursor refers to.
+fn definition(uri: &str, text: &str, line: u32, character: u32) -> Json {
+    let index = LineIndex::new(text);
+    let (tokens, _) = lexer::tokenize_lossless(text);
+    let Ok(tree) = cst::parse(tokens) else {
+        return Json::Null;
+    };
+    let (occurrences, target) = occurrences(&tree, index.offset(line, character));
+    let declaration = target.and_then(|t| occurrences[t].declaration);
+    match occurrences.iter().find(|o| Some(o.offset) == declaration) {
+        Some(parameter) => location(uri, &index, parameter),
+        None => Json::Null,
+    }
+}
+
+/// Every occurrence of the variable under the cursor, and its parameter if
+/// `declaration` is set. A parameter hides others with the same name, so only
+/// uses that resolve to the same one count; names no function declares are
+/// matched by name.
+fn references(uri: &str, text: &str, line: u32, character: u32, declaration: bool) -> Vec<Json> {
+    let index = LineIndex::new(text);
+    let (tokens, _) = lexer::tokenize_lossless(text);
+    let Ok(tree) = cst::parse(tokens) else {
+        return Vec::new();
+    };
+    let (occurrences, target) = occurrences(&tree, index.offset(line, character));
+    let Some(target) = target.map(|t| &occurrences[t]) else {
+        return Vec::new();
+    };
+    occurrences
+        .iter()
+        .filter(|o| o.declaration == target.declaration && (o.declaration.is_some() || o.name == target.name))
+        .filter(|o| declaration || o.declaration != Some(o.offset))
+        .map(|o| location(uri, &index, o))
+        .collect()
+}
+
+/// The first or the last token under `node`.
+fn edge_token(mut node: &Node, last: bool) -> Option<&LosslessToken> {
+    loop {
+        match if last { node.children.last() } else { node.children.first() }? {
+            Element::Node(n) => node = n,
+            Element::Token(t) => return Some(t),
+        }
+    }
+}
+
+/// The functions under `node` as document symbols, each with its parameters
+/// and the functions written inside it as children.
+fn symbols(node: &Node, index: &LineIndex) -> Vec<Json> {
+    let mut found = Vec::new();
+    for child in node.children.iter() {
+        let Element::Node(n) = child else {
+            continue;
+        };
+        if n.kind != NodeKind::Function {
+            found.extend(symbols(n, index));
+            continue;
+        }
+        let (Some(first), Some(last)) = (edge_token(n, false), edge_token(n, true)) else {
+            continue;
+        };
+        let mut children: Vec<Json> = parameters(n)
+            .into_iter()
+            .map(|(name, offset)| {
+                let range = range(index, offset, offset + name.len());
+                Json::object(vec![
+                    ("name", name.into()),
+                    ("kind", SYMBOL_VARIABLE.into()),
+                    ("range", range.clone()),
+                    ("selectionRange", range),
+                ])
+            })
+            .collect();
+        children.extend(symbols(n, index));
+        // Named after the line of the `fun` or `=>`, like the profiler does
+        let keyword = n.children.iter().find_map(|c| match c {
+            Element::Token(t) if matches!(t.token.variant, TokenVariant::Fun | TokenVariant::EqualGreater) => Some(t),
+            _ => None,
+        });
+        let start = first.token.offset;
+        found.push(Json::object(vec![
+            ("name", evaluator::function_label(keyword.unwrap_or(first).token.line).into()),
+            ("kind", SYMBOL_FUNCTION.into()),
+            ("range", range(index, start, last.token.offset + last.token.lexeme.len())),
+            ("selectionRange", range(index, start, start + first.token.lexeme.len())),
+            ("children", children.into()),
+        ]));
+    }
+    found
+}
+
+fn document_symbols(text: &str) -> Json {
+    let index = LineIndex::new(text);
+    let (tokens, _) = lexer::tokenize_lossless(text);
+    match cst::parse(tokens) {
+        Ok(tree) => symbols(&tree, &index).into(),
+        Err(_) => Json::Array(Vec::new()),
+    }
+}
+
+fn capabilities() -> Json {
+    Json::object(vec![
+        ("textDocumentSync", 1u32.into()),
+        ("hoverProvider", true.into()),
+        ("definitionProvider", true.into()),
+        ("referencesProvider", true.into()),
+        ("documentSymbolProvider", true.into()),
+        (
+            "semanticTokensProvider",
+            Json::object(vec![
+                (
+                    "legend",
+                    Json::object(vec![
+                        ("tokenTypes", TOKEN_TYPES.map(Json::from).to_vec().into()),
+                        ("tokenModifiers", Json::Array(Vec::new())),
+                    ]),
+                ),
+                ("full", true.into()),
+            ]),
+        ),
+    ])
+}
+
+struct Server {
+    documents: HashMap<String, String>,
+    shutdown: bool,
+}
+
+impl Server {
+    fn publish_diagnostics(&self, uri: &str) {
+        let diagnostics = self.documents.get(uri).map_or(Vec::new(), |text| diagnostics(text));
+        write_message(&Json::object(vec![
+            ("jsonrpc", "2.0".into()),
+            ("method", "textDocument/publishDiagnostics".into()),
+            (
+                "params",
+                Json::object(vec![("uri", uri.into()), ("diagnostics", diagnostics.into())]),
+            ),
+        ]));
+    }
+
+    /// Handles one request, returning its result or a JSON-RPC error code and message.
+    fn request(&mut self, method: &str, params: &Json) -> Result<Json, (i32, String)> {
+        let uri = params.get("textDocument").get("uri").as_str().unwrap_or_default().to_owned();
+        let line = params.get("position").get("line").as_u64().unwrap_or(0) as u32;
+        let character = params.get("position").get("character").as_u64().unwrap_or(0) as u32;
+        let text = self.documents.get(&uri).cloned().unwrap_or_default();
+        match method {
+            "initialize" => Ok(Json::object(vec![
+                ("capabilities", capabilities()),
+                ("serverInfo", Json::object(vec![("name", "lox".into())])),
+            ])),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Json::Null)
+            }
+            "textDocument/semanticTokens/full" => {
+                Ok(Json::object(vec![("data", semantic_tokens(&text).into())]))
+            }
+            "textDocument/hover" => Ok(hover(&text, line, character)),
+            "textDocument/references" => {
+                let declaration = !matches!(params.get("context").get("includeDeclaration"), Json::Bool(false));
+                Ok(references(&uri, &text, line, character, declaration).into())
+            }
+            "textDocument/definition" => Ok(definition(&uri, &text, line, character)),
+            "textDocument/documentSymbol" => Ok(document_symbols(&text)),
+            _ => Err((-32601, format!("Unhandled method {method}"))),
+        }
+    }
+
+    fn notification(&mut self, method: &str, params: &Json) {
+        let uri = params.get("textDocument").get("uri").as_str().unwrap_or_default().to_owned();
+        match method {
+            "textDocument/didOpen" => {
+                let text = params.get("textDocument").get("text").as_str().unwrap_or_default();
+                self.documents.insert(uri.clone(), text.to_owned());
+                self.publish_diagnostics(&uri);
+            }
+            "textDocument/didChange" => {
+                // Full synchronization: the last change holds the whole document
+                if let Some(change) = params.get("contentChanges").as_array().last() {
+                    let text = change.get("text").as_str().unwrap_or_default();
+                    self.documents.insert(uri.clone(), text.to_owned());
+                }
+                self.publish_diagnostics(&uri);
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                self.publish_diagnostics(&uri);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Serves the Language Server Protocol over stdio until the client sends `exit`.
+/// Returns the process exit code.
+pub fn run() -> i32 {
+    let mut server = Server {
+        documents: HashMap::new(),
+        shutdown: false,
+    };
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    loop {
+        let message = match read_message(&mut input) {
+            Ok(Some(message)) => message,
+            Ok(None) => return 1,
+            Err(e) => {
+                eprintln!("{}", e);
+                continue;
+            }
+        };
+        let method = message.get("method").as_str().unwrap_or_default().to_owned();
+        let params = message.get("params");
+        let id = message.get("id");
+        if method == "exit" {
+            return if server.shutdown { 0 } else { 1 };
+        }
+        if id.is_null() {
+            server.notification(&method, params);
+            continue;
+        }
+        let response = match server.request(&method, params) {
+            Ok(result) => Json::object(vec![
+                ("jsonrpc", "2.0".into()),
+                ("id", id.clone()),
+                ("result", result),
+            ]),
+            Err((code, error)) => Json::object(vec![
+                ("jsonrpc", "2.0".into()),
+                ("id", id.clone()),
+                (
+                    "error",
+                    Json::object(vec![
+                        ("code", Json::Number(code as f64)),
+                        ("message", error.into()),
+                    ]),
+                ),
+            ]),
+        };
+        write_message(&response);
+    }
+}
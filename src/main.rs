@@ -1,29 +1,128 @@
 mod lexer;
 mod parser;
 mod evaluator;
+mod cst;
+mod formatter;
+mod json;
+mod lsp;
+mod linter;
+mod optimizer;
+mod debugger;
+mod dap;
+mod profiler;
+mod coverage;
+mod tester;
+mod fuzzer;
 
+use std::collections::HashSet;
 use std::env;
 use std::fs;
-use std::io::{self, Write};
+use std::io;
+use std::panic;
 use std::process::exit;
+use std::thread;
+
+/// Prints syntax errors and exits. Nesting too deep to handle exits like
+/// the runtime error it would otherwise have become.
+fn exit_with_syntax_errors(errors: Vec<parser::SyntaxError>) -> ! {
+    let code = if errors.iter().any(|e| e.is_stack_overflow()) { 70 } else { 65 };
+    for error in errors {
+        eprintln!("{}", error);
+    }
+    exit(code);
+}
+
+/// The value following `name` in `options`, if the option is present.
+fn option_value<'a>(options: &'a [String], name: &str) -> Option<&'a str> {
+    let i = options.iter().position(|o| o == name)?;
+    match options.get(i + 1) {
+        Some(value) => Some(value),
+        None => {
+            eprintln!("Expect a value after {}", name);
+            exit(64);
+        }
+    }
+}
+
+/// The `--max-depth <n>` option, or the default nesting limit.
+fn max_depth(options: &[String]) -> usize {
+    match option_value(options, "--max-depth") {
+        Some(n) => n.parse().unwrap_or_else(|_| {
+            eprintln!("Expect a number after --max-depth");
+            exit(64);
+        }),
+        None => parser::MAX_DEPTH,
+    }
+}
+
+/// The interpreter settings given by `--max-depth <n>` and `--strict`.
+fn interpreter(options: &[String]) -> evaluator::Interpreter {
+    evaluator::Interpreter {
+        max_depth: max_depth(options),
+        strict: options.iter().any(|a| a == "--strict"),
+    }
+}
+
+/// Native stack reserved for each level of `--max-depth`, and on top of it.
+const STACK_PER_LEVEL: usize = 16 * 1024;
+const STACK_BASE: usize = 8 * 1024 * 1024;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
+    // Parsing and evaluation recurse once per nesting level, so the stack
+    // has to grow with --max-depth for the limit to be hit before it runs out
+    let depth = max_depth(args.get(1..).unwrap_or_default());
+    let stack = depth.saturating_mul(STACK_PER_LEVEL).saturating_add(STACK_BASE);
+    let worker = thread::Builder::new().stack_size(stack).spawn(move || run(args));
+    let worker = worker.unwrap_or_else(|_| {
+        eprintln!("Cannot allocate a stack for --max-depth {}", depth);
+        exit(64);
+    });
+    if let Err(panic) = worker.join() {
+        panic::resume_unwind(panic);
+    }
+}
+
+fn run(args: Vec<String>) {
+    if args.get(1).is_some_and(|command| command == "lsp") {
+        exit(lsp::run());
+    }
+    if args.get(1).is_some_and(|command| command == "dap") {
+        exit(dap::run(interpreter(&args[2..])));
+    }
     if args.len() < 3 {
-        writeln!(io::stderr(), "Usage: {} tokenize <filename>", args[0]).unwrap();
+        eprintln!("Usage: {} tokenize <filename>", args[0]);
         return;
     }
 
     let command = &args[1];
     let filename = &args[2];
+    if command == "test" {
+        exit(tester::run_tests(filename));
+    }
+    if command == "fuzz" {
+        if !fuzzer::TARGETS.contains(&filename.as_str()) {
+            eprintln!("Unknown fuzz target: {} (expect one of {})", filename, fuzzer::TARGETS.join(", "));
+            exit(64);
+        }
+        let number = |name: &str, default: u64| match option_value(&args[3..], name) {
+            Some(n) => n.parse().unwrap_or_else(|_| {
+                eprintln!("Expect a number after {}", name);
+                exit(64);
+            }),
+            None => default,
+        };
+        let (iterations, seed) = (number("--iterations", 10_000), number("--seed", 1));
+        exit(fuzzer::fuzz(filename, iterations, seed, option_value(&args[3..], "--save")));
+    }
     let file_contents = fs::read_to_string(filename).unwrap_or_else(|_| {
-        writeln!(io::stderr(), "Failed to read file {}", filename).unwrap();
+        eprintln!("Failed to read file {}", filename);
         String::new()
     });
 
     match command.as_str() {
         "tokenize" => {
-            writeln!(io::stderr(), "Results from lexer").unwrap();
+            eprintln!("Results from lexer");
             let (tokens, errors) = lexer::tokenize(&file_contents);
             for token in &tokens {
                 println!("{}", token);
@@ -35,8 +134,22 @@ fn main() {
                 exit(65);
             }
         }
-        "parse" => {
-            writeln!(io::stderr(), "Results from parser").unwrap();
+        "cst" => {
+            eprintln!("Results from lossless parser");
+            let (tokens, errors) = lexer::tokenize_lossless(&file_contents);
+            let tree = cst::parse_with_max_depth(tokens, max_depth(&args[3..]))
+                .unwrap_or_else(|e| exit_with_syntax_errors(vec![e]));
+            debug_assert_eq!(tree.text(), file_contents, "the CST must round-trip the source");
+            print!("{}", tree);
+            if let Some(e) = errors {
+                for error in e {
+                    eprintln!("{}", error);
+                }
+                exit(65);
+            }
+        }
+        "fmt" => {
+            let check = args[3..].iter().any(|a| a == "--check");
             let (tokens, errors) = lexer::tokenize(&file_contents);
             if let Some(e) = errors {
                 for error in e {
@@ -44,22 +157,99 @@ fn main() {
                 }
                 exit(65);
             }
-            match parser::parse(&tokens) {
-                Ok(abstract_syntax_tree) => {
-                    if let Some(x) = abstract_syntax_tree.root {
-                        println!("{}", x.borrow().to_string());
+            let max_depth = max_depth(&args[3..]);
+            if let Err(e) = parser::parse_with_max_depth(&tokens, max_depth) {
+                exit_with_syntax_errors(e);
+            }
+            let (tokens, _) = lexer::tokenize_lossless(&file_contents);
+            let tree = cst::parse_with_max_depth(tokens, max_depth).unwrap_or_else(|e| exit_with_syntax_errors(vec![e]));
+            let formatted = formatter::format(&tree);
+            if formatted != file_contents {
+                if check {
+                    eprintln!("{} is not formatted", filename);
+                    exit(1);
+                }
+                fs::write(filename, formatted).unwrap_or_else(|_| {
+                    eprintln!("Failed to write file {}", filename);
+                    exit(74);
+                });
+            }
+        }
+        "lint" => {
+            let rules = linter::rules();
+            let all: HashSet<&str> = rules.iter().map(|r| r.name()).collect();
+            let mut enabled = all.clone();
+            let mut as_json = false;
+            let mut options = args[3..].iter();
+            while let Some(option) = options.next() {
+                let value = options.next().map(String::as_str).unwrap_or_default();
+                match (option.as_str(), value) {
+                    ("--format", "json") => as_json = true,
+                    ("--format", "text") => as_json = false,
+                    ("--enable", "all") => enabled = all.clone(),
+                    ("--disable", "all") => enabled.clear(),
+                    ("--enable", rule) if all.contains(rule) => {
+                        enabled.insert(all.get(rule).unwrap());
+                    }
+                    ("--disable", rule) if all.contains(rule) => {
+                        enabled.remove(rule);
+                    }
+                    ("--enable" | "--disable", rule) => {
+                        eprintln!("Unknown lint rule: {}", rule);
+                        exit(64);
+                    }
+                    _ => {
+                        eprintln!("Unknown option: {} {}", option, value);
+                        exit(64);
                     }
                 }
-                Err(e) => {
-                    for error in e {
-                        eprintln!("{}", error);
+            }
+            let (tokens, errors) = lexer::tokenize(&file_contents);
+            if let Some(e) = errors {
+                for error in e {
+                    eprintln!("{}", error);
+                }
+                exit(65);
+            }
+            let ast = parser::parse(&tokens).unwrap_or_else(|e| exit_with_syntax_errors(e));
+            let (tokens, _) = lexer::tokenize_lossless(&file_contents);
+            let warnings = linter::lint(&ast, &tokens, &enabled);
+            if as_json {
+                let warnings: Vec<json::Json> = warnings.iter().map(|w| w.to_json()).collect();
+                println!("{}", json::Json::from(warnings));
+            } else {
+                for warning in warnings.iter() {
+                    println!("{}", warning);
+                }
+            }
+            if !warnings.is_empty() {
+                exit(1);
+            }
+        }
+        "parse" => {
+            eprintln!("Results from parser");
+            let (tokens, errors) = lexer::tokenize(&file_contents);
+            if let Some(e) = errors {
+                for error in e {
+                    eprintln!("{}", error);
+                }
+                exit(65);
+            }
+            let interpreter = interpreter(&args[3..]);
+            match parser::parse_with_max_depth(&tokens, interpreter.max_depth) {
+                Ok(mut abstract_syntax_tree) => {
+                    if args[3..].iter().any(|a| a == "--optimized") {
+                        optimizer::optimize(&mut abstract_syntax_tree, &interpreter);
+                    }
+                    if let Some(x) = abstract_syntax_tree.root {
+                        println!("{}", x.borrow());
                     }
-                    exit(65);
                 }
+                Err(e) => exit_with_syntax_errors(e),
             }
         }
         "evaluate" => {
-            writeln!(io::stderr(), "Results from evaluator").unwrap();
+            eprintln!("Results from evaluator");
             let (tokens, errors) = lexer::tokenize(&file_contents);
             if let Some(e) = errors {
                 for error in e {
@@ -67,9 +257,44 @@ fn main() {
                 }
                 exit(65);
             }
-            match parser::parse(&tokens) {
+            let interpreter = interpreter(&args[3..]);
+            match parser::parse_with_max_depth(&tokens, interpreter.max_depth) {
                 Ok(mut abstract_syntax_tree) => {
-                    match evaluator::evaluate(&mut abstract_syntax_tree) {
+                    let coverage_file = option_value(&args[3..], "--coverage");
+                    let mut coverage = coverage_file.map(|_| coverage::Coverage::new(&abstract_syntax_tree));
+                    // Folded lines would never be reported as executed
+                    if coverage.is_none() {
+                        optimizer::optimize(&mut abstract_syntax_tree, &interpreter);
+                    }
+                    let mut profiler = args[3..]
+                        .iter()
+                        .any(|a| a == "--profile")
+                        .then(profiler::Profiler::new);
+                    let mut hooks: Vec<&mut dyn evaluator::Hooks> = Vec::new();
+                    if let Some(p) = profiler.as_mut() {
+                        hooks.push(p);
+                    }
+                    if let Some(c) = coverage.as_mut() {
+                        hooks.push(c);
+                    }
+                    let result = interpreter.evaluate_with_hooks(&abstract_syntax_tree, &mut hooks);
+                    if let Some(p) = profiler {
+                        eprintln!("{}", p.report());
+                        let stacks = format!("{}.folded", filename);
+                        fs::write(&stacks, p.collapsed_stacks()).unwrap_or_else(|_| {
+                            eprintln!("Failed to write file {}", stacks);
+                            exit(74);
+                        });
+                        eprintln!("Collapsed stacks written to {}", stacks);
+                    }
+                    if let (Some(c), Some(file)) = (coverage, coverage_file) {
+                        let source = fs::canonicalize(filename).map_or(filename.clone(), |p| p.display().to_string());
+                        fs::write(file, c.lcov(&source)).unwrap_or_else(|_| {
+                            eprintln!("Failed to write file {}", file);
+                            exit(74);
+                        });
+                    }
+                    match result {
                         Ok(output) => println!("{}", output.value_print()),
                         Err(e) => {
                             for error in e {
@@ -79,18 +304,34 @@ fn main() {
                         }
                     }
                 }
+                Err(e) => exit_with_syntax_errors(e),
+            }
+
+        }
+        "debug" => {
+            let (tokens, errors) = lexer::tokenize(&file_contents);
+            if let Some(e) = errors {
+                for error in e {
+                    eprintln!("{}", error);
+                }
+                exit(65);
+            }
+            let interpreter = interpreter(&args[3..]);
+            let abstract_syntax_tree = parser::parse_with_max_depth(&tokens, interpreter.max_depth)
+                .unwrap_or_else(|e| exit_with_syntax_errors(e));
+            let mut debugger = debugger::Debugger::new(io::stdin().lock(), &file_contents, interpreter.clone());
+            match interpreter.evaluate_with_hooks(&abstract_syntax_tree, &mut debugger) {
+                Ok(output) => println!("{}", output.value_print()),
                 Err(e) => {
                     for error in e {
                         eprintln!("{}", error);
                     }
-                    exit(65);
+                    exit(70);
                 }
             }
-
         }
         _ => {
-            writeln!(io::stderr(), "Unknown command: {}", command).unwrap();
-            return;
+            eprintln!("Unknown command: {}", command);
         }
     }
 }
@@ -0,0 +1,79 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::evaluator::Interpreter;
+use crate::lexer::TokenVariant;
+use crate::parser::{self, Tree, TreeNode};
+
+fn is_literal(node: &TreeNode) -> bool {
+    node.left.is_none()
+        && node.right.is_none()
+        && matches!(
+            node.value.variant,
+            TokenVariant::Number(_)
+                | TokenVariant::Integer(_)
+                | TokenVariant::String(_)
+                | TokenVariant::True
+                | TokenVariant::False
+                | TokenVariant::Nil
+        )
+}
+
+/// Folds the children first, then this node if all of its operands became
+/// literals. Calls, list literals and functions are never folded themselves:
+/// their items only look like comma operators, and a list or a function is a
+/// new value each time.
+fn fold(node: &Rc<RefCell<TreeNode>>, interpreter: &Interpreter) {
+    let (variant, left, right) = {
+        let n = node.borrow();
+        (n.value.variant.clone(), n.left.clone(), n.right.clone())
+    };
+    match variant {
+        TokenVariant::LeftBracket if left.is_none() => {
+            for child in parser::list(&right) {
+                fold(&child, interpreter);
+            }
+            return;
+        }
+        TokenVariant::LeftParen => {
+            for child in left.iter().chain(parser::list(&right).iter()) {
+                fold(child, interpreter);
+            }
+            return;
+        }
+        TokenVariant::Fun | TokenVariant::EqualGreater => {
+            if let Some(body) = &right {
+                fold(body, interpreter);
+            }
+            return;
+        }
+        _ => {}
+    }
+    for child in [&left, &right].into_iter().flatten() {
+        fold(child, interpreter);
+    }
+    if right.is_none() || ![&left, &right].into_iter().flatten().all(|c| is_literal(&c.borrow())) {
+        return;
+    }
+    let mut subtree = Tree::new();
+    subtree.root = Some(Rc::clone(node));
+    // Operations that fail are kept as they are, so they fail the same way at run time
+    if let Ok(mut value) = interpreter.evaluate(&subtree) {
+        if value.lexeme.is_empty() {
+            // Results such as `true` are built without a lexeme, but the tree printer needs one
+            value.lexeme = value.value_print();
+        }
+        let mut n = node.borrow_mut();
+        n.value = value;
+        n.left = None;
+        n.right = None;
+    }
+}
+
+/// Replaces every operation on literals, such as `(1 + 2) * 3`, `"a" + "b"`
+/// or `!nil`, with its result as `interpreter` would compute it.
+pub fn optimize(ast: &mut Tree, interpreter: &Interpreter) {
+    if let Some(root) = &ast.root {
+        fold(root, interpreter);
+    }
+}
@@ -4,6 +4,13 @@ use std::rc::Rc;
 
 use crate::lexer::{Token, TokenVariant};
 
+/// How deeply expressions may nest before parsing or evaluating them reports
+/// a stack overflow instead of exhausting the real stack.
+pub const MAX_DEPTH: usize = 1000;
+
+/// Most parameters a function may declare, and arguments a call may pass.
+const MAX_ARGUMENTS: usize = 255;
+
 #[derive(PartialEq, Clone, Debug)]
 pub struct TreeNode {
     pub value: Token,
@@ -21,41 +28,94 @@ impl TreeNode {
             group_count,
         }
     }
+}
 
-    fn is_structured(&self) -> bool {
-        match (&self.left, &self.right) {
-            (Some(_), None) => false,
-            (Some(a), Some(b)) => a.borrow().is_structured() && b.borrow().is_structured(),
-            (None, Some(b)) => b.borrow().is_structured(),
-            (None, None) => true,
+impl Drop for TreeNode {
+    fn drop(&mut self) {
+        // Freeing a deep tree recursively could itself overflow the stack
+        let mut orphans: Vec<_> = [self.left.take(), self.right.take()].into_iter().flatten().collect();
+        while let Some(node) = orphans.pop() {
+            if let Ok(cell) = Rc::try_unwrap(node) {
+                let mut node = cell.into_inner();
+                orphans.extend([node.left.take(), node.right.take()].into_iter().flatten());
+            }
         }
     }
 }
 
-impl ToString for TreeNode {
-    fn to_string(&self) -> String {
+/// The items of a parameter, argument or list literal, which are chained
+/// together with `,` nodes the way the comma operator is.
+pub fn list(node: &Option<Rc<RefCell<TreeNode>>>) -> Vec<Rc<RefCell<TreeNode>>> {
+    let mut items = Vec::new();
+    let mut next = node.clone();
+    while let Some(node) = next {
+        let n = node.borrow();
+        match (&n.value.variant, &n.left, &n.right, n.group_count) {
+            (TokenVariant::Comma, Some(left), Some(right), 0) => {
+                items.push(Rc::clone(right));
+                next = Some(Rc::clone(left));
+            }
+            _ => {
+                items.push(Rc::clone(&node));
+                next = None;
+            }
+        }
+    }
+    items.reverse();
+    items
+}
+
+impl fmt::Display for TreeNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match (&self.left, &self.right, self.group_count) {
-            (None, None, 0) => format!("{}", self.value.short_print()),
-            (Some(left), Some(right), 0) => format!(
+            // A call, as in `f(1, 2)`
+            (Some(callee), arguments, 0) if self.value.variant == TokenVariant::LeftParen => {
+                write!(f, "(call {}", callee.borrow())?;
+                for argument in list(arguments) {
+                    write!(f, " {}", argument.borrow())?;
+                }
+                write!(f, ")")
+            }
+            // An index, as in `xs[0]`
+            (Some(list), Some(index), 0) if self.value.variant == TokenVariant::LeftBracket => {
+                write!(f, "(index {} {})", list.borrow(), index.borrow())
+            }
+            // A list literal, as in `[1, 2]`
+            (None, items, 0) if self.value.variant == TokenVariant::LeftBracket => {
+                write!(f, "(list")?;
+                for item in list(items) {
+                    write!(f, " {}", item.borrow())?;
+                }
+                write!(f, ")")
+            }
+            // A function, as in `fun (a) { return a; }` or `(a) => a`
+            (parameters, body, 0)
+                if matches!(self.value.variant, TokenVariant::Fun | TokenVariant::EqualGreater) =>
+            {
+                let parameters: Vec<String> = list(parameters).iter().map(|p| p.borrow().to_string()).collect();
+                write!(f, "({} ({}) ", self.value.short_print(), parameters.join(" "))?;
+                match body {
+                    Some(body) => write!(f, "{})", body.borrow()),
+                    None => write!(f, "nil)"),
+                }
+            }
+            (None, None, 0) => write!(f, "{}", self.value.short_print()),
+            (Some(left), Some(right), 0) => write!(
+                f,
                 "({} {} {})",
                 self.value.short_print(),
-                left.borrow().to_string(),
-                right.borrow().to_string()
+                left.borrow(),
+                right.borrow()
             ),
             (None, Some(right), 0) => {
-                format!(
-                    "({} {})",
-                    self.value.short_print(),
-                    right.borrow().to_string()
-                )
+                write!(f, "({} {})", self.value.short_print(), right.borrow())
             }
-            (Some(_), None, _) => {
-                panic!("Invalid tree structure");
-            }
-            (_, _, _) => format!("(group {})", {
+            // Postfix operators, as in `x++`
+            (Some(left), None, 0) => write!(f, "({} {})", left.borrow(), self.value.short_print()),
+            (_, _, _) => write!(f, "(group {})", {
                 let mut copy = self.clone();
                 copy.group_count -= 1;
-                copy.to_string()
+                copy
             }),
         }
     }
@@ -75,7 +135,16 @@ impl Tree {
 #[derive(Debug)]
 enum SyntaxErrorVariant {
     UnmatchedParentheses,
-    ExpectExpression(u32, char),
+    ExpectExpression(u32, String),
+    ExpectEnd(u32, String),
+    InvalidAssignmentTarget(u32, String),
+    /// What was expected instead of the token, as in `')' after arguments`
+    Expect(u32, String, &'static str),
+    /// What there are more than `MAX_ARGUMENTS` of
+    TooMany(u32, String, &'static str),
+    DuplicateParameter(u32, String),
+    OutsideLoop(u32, String),
+    StackOverflow(u32),
 }
 
 impl fmt::Display for SyntaxErrorVariant {
@@ -83,192 +152,559 @@ impl fmt::Display for SyntaxErrorVariant {
         match self {
             SyntaxErrorVariant::UnmatchedParentheses => write!(f, "Unmatched parentheses."),
             SyntaxErrorVariant::ExpectExpression(_, _) => write!(f, "Expect expression."),
+            SyntaxErrorVariant::ExpectEnd(_, _) => write!(f, "Expect end of expression."),
+            SyntaxErrorVariant::InvalidAssignmentTarget(_, _) => write!(f, "Invalid assignment target."),
+            SyntaxErrorVariant::Expect(_, _, expected) => write!(f, "Expect {expected}."),
+            SyntaxErrorVariant::TooMany(_, _, what) => write!(f, "Can't have more than {MAX_ARGUMENTS} {what}."),
+            SyntaxErrorVariant::DuplicateParameter(_, _) => {
+                write!(f, "Already a variable with this name in this scope.")
+            }
+            SyntaxErrorVariant::OutsideLoop(_, keyword) => write!(f, "Can't use '{keyword}' outside of a loop."),
+            SyntaxErrorVariant::StackOverflow(_) => write!(f, "Stack overflow."),
         }
     }
 }
 
 #[derive(Debug)]
 pub struct SyntaxError {
-    variant: SyntaxErrorVariant,
+    /// Boxed, since every level of the parser's recursion passes one around
+    /// and a small `Result` keeps its frames small
+    variant: Box<SyntaxErrorVariant>,
 }
 
 impl SyntaxError {
     fn new(variant: SyntaxErrorVariant) -> Self {
-        Self { variant }
+        Self { variant: Box::new(variant) }
+    }
+
+    /// For the lossless parser, which checks nesting the same way.
+    pub fn stack_overflow(line: u32) -> Self {
+        Self::new(SyntaxErrorVariant::StackOverflow(line))
+    }
+
+    pub fn line(&self) -> Option<u32> {
+        match *self.variant {
+            SyntaxErrorVariant::UnmatchedParentheses => None,
+            SyntaxErrorVariant::ExpectExpression(line, _) => Some(line),
+            SyntaxErrorVariant::ExpectEnd(line, _) => Some(line),
+            SyntaxErrorVariant::InvalidAssignmentTarget(line, _) => Some(line),
+            SyntaxErrorVariant::Expect(line, _, _) => Some(line),
+            SyntaxErrorVariant::TooMany(line, _, _) => Some(line),
+            SyntaxErrorVariant::DuplicateParameter(line, _) => Some(line),
+            SyntaxErrorVariant::OutsideLoop(line, _) => Some(line),
+            SyntaxErrorVariant::StackOverflow(line) => Some(line),
+        }
+    }
+
+    /// Nesting too deep to handle is a runtime failure rather than a mistake
+    /// in the code, so callers exit like they would for a runtime error.
+    pub fn is_stack_overflow(&self) -> bool {
+        matches!(*self.variant, SyntaxErrorVariant::StackOverflow(_))
+    }
+
+    pub fn message(&self) -> String {
+        self.variant.to_string()
     }
 }
 
 impl fmt::Display for SyntaxError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self.variant {
+        match &*self.variant {
             SyntaxErrorVariant::UnmatchedParentheses => write!(f, "Error: {}", self.variant),
-            SyntaxErrorVariant::ExpectExpression(line, ch) => {
-                write!(f, "[line {line}] Error at '{ch}': {}", self.variant)
+            SyntaxErrorVariant::ExpectExpression(line, lexeme)
+            | SyntaxErrorVariant::ExpectEnd(line, lexeme)
+            | SyntaxErrorVariant::Expect(line, lexeme, _)
+            | SyntaxErrorVariant::TooMany(line, lexeme, _)
+                if lexeme.is_empty() =>
+            {
+                write!(f, "[line {line}] Error at end: {}", self.variant)
+            }
+            SyntaxErrorVariant::ExpectExpression(line, lexeme)
+            | SyntaxErrorVariant::ExpectEnd(line, lexeme)
+            | SyntaxErrorVariant::InvalidAssignmentTarget(line, lexeme)
+            | SyntaxErrorVariant::Expect(line, lexeme, _)
+            | SyntaxErrorVariant::TooMany(line, lexeme, _)
+            | SyntaxErrorVariant::DuplicateParameter(line, lexeme)
+            | SyntaxErrorVariant::OutsideLoop(line, lexeme) => {
+                write!(f, "[line {line}] Error at '{lexeme}': {}", self.variant)
             }
+            // Printed like the runtime error it stands in for
+            SyntaxErrorVariant::StackOverflow(line) => write!(f, "{}\n[line {line}] in script", self.variant),
         }
     }
 }
 
-#[derive(Debug, PartialEq)]
-enum TreeManipulation {
-    Root,
-    Operator,
-    RightChild,
-}
-
-use TreeManipulation::*;
-
-fn get_index_of_closing_paren(tokens: &Vec<Token>, start: usize) -> Option<usize> {
-    let mut stack_size = 0;
-    for (t_index, t) in tokens.iter().enumerate().skip(start) {
+/// Indexes of the `(` that are never closed, in order.
+fn unclosed_parentheses(tokens: &[Token]) -> Vec<usize> {
+    let mut open = Vec::new();
+    for (i, t) in tokens.iter().enumerate() {
         match t.variant {
-            TokenVariant::LeftParen => {
-                stack_size += 1;
-            }
+            TokenVariant::LeftParen => open.push(i),
             TokenVariant::RightParen => {
-                stack_size -= 1;
-                if stack_size == 0 {
-                    return Some(t_index);
-                }
+                open.pop();
             }
             _ => {}
         }
     }
+    open
+}
+
+/// Line of the first node found deeper than `max_depth`. Long chains of
+/// operators are parsed in a loop but still give deep trees.
+fn too_deep(root: &Rc<RefCell<TreeNode>>, max_depth: usize) -> Option<u32> {
+    let mut pending = vec![(Rc::clone(root), 1)];
+    while let Some((node, depth)) = pending.pop() {
+        let node = node.borrow();
+        if depth > max_depth {
+            return Some(node.value.line);
+        }
+        for child in [&node.left, &node.right].into_iter().flatten() {
+            pending.push((Rc::clone(child), depth + 1));
+        }
+    }
     None
 }
 
-fn parse_sub_expression(
-    tokens: &Vec<Token>,
-    index: &mut usize,
-) -> Result<Rc<RefCell<TreeNode>>, Vec<SyntaxError>> {
-    if let Some(pos) = get_index_of_closing_paren(tokens, *index) {
-        match parse(&tokens[*index + 1..=pos].to_vec()) {
-            Ok(ref new_tree) => {
-                if let Some(ref new_node) = new_tree.root {
-                    new_node.borrow_mut().group_count += 1;
-                    *index = pos;
-                    return Ok(Rc::clone(&new_node));
-                }
-            }
-            Err(e) => return Err(e),
+fn expect_expression(t: &Token) -> SyntaxError {
+    SyntaxError::new(SyntaxErrorVariant::ExpectExpression(t.line, t.lexeme.clone()))
+}
+
+/// Only variables and list elements can be assigned to, and not through
+/// parentheses.
+fn assignable(target: &Rc<RefCell<TreeNode>>, operator: &Token) -> Result<(), SyntaxError> {
+    let target = target.borrow();
+    match (&target.value.variant, &target.left, &target.right, target.group_count) {
+        (TokenVariant::Identifier, None, None, 0) => Ok(()),
+        (TokenVariant::LeftBracket, Some(_), Some(_), 0) => Ok(()),
+        _ => Err(SyntaxError::new(SyntaxErrorVariant::InvalidAssignmentTarget(
+            operator.line,
+            operator.lexeme.clone(),
+        ))),
+    }
+}
+
+/// Adds `item` to the end of a parameter or argument list (see `list()`).
+fn push(list: Option<Rc<RefCell<TreeNode>>>, comma: &Token, item: Rc<RefCell<TreeNode>>) -> Rc<RefCell<TreeNode>> {
+    match list {
+        None => item,
+        Some(list) => {
+            let mut node = TreeNode::new(comma.clone(), 0);
+            node.left = Some(list);
+            node.right = Some(item);
+            Rc::new(RefCell::new(node))
         }
     }
-    Err(vec![SyntaxError::new(
-        SyntaxErrorVariant::UnmatchedParentheses,
-    )])
 }
 
-pub fn parse(tokens: &Vec<Token>) -> Result<Tree, Vec<SyntaxError>> {
-    let mut errors = Vec::new();
-    let mut ast = Tree::new();
-    let mut i = 0;
-    let mut current: Option<Rc<RefCell<TreeNode>>> = None;
-    let mut tm = Root;
-    let mut last_precedence: u32 = 99;
-    while i < tokens.len() {
-        match tokens[i].variant {
-            TokenVariant::Eof | TokenVariant::RightParen => {}
-            _ if tokens[i].is_unary_operator() && tm != Operator => match tm {
-                Root => {
-                    let new_node = Rc::new(RefCell::new(TreeNode::new(tokens[i].clone(), 0)));
-                    ast.root = Some(Rc::clone(&new_node));
-                    current = Some(Rc::clone(&new_node));
-                    tm = RightChild;
-                }
-                RightChild => match &current {
-                    Some(current_node) => {
-                        let new_node = Rc::new(RefCell::new(TreeNode::new(tokens[i].clone(), 0)));
-                        current_node.borrow_mut().right = Some(Rc::clone(&new_node));
-                        current = Some(Rc::clone(&new_node));
-                    }
-                    _ => panic!("Expected the current node to be populated at this point."),
-                },
-                _ => panic!("Unhandled unary case: {tm:?}"),
-            },
-            _ if tokens[i].is_binary_operator() => match tm {
-                Root => errors.push(SyntaxError::new(SyntaxErrorVariant::ExpectExpression(
-                    tokens[i].line,
-                    tokens[i].lexeme.chars().last().unwrap(),
-                ))),
-                Operator if tokens[i].get_precedence() > last_precedence => {
-                    let new_node = Rc::new(RefCell::new(TreeNode::new(tokens[i].clone(), 0)));
-                    match &ast.root {
-                        Some(root_node) => {
-                            match &root_node.borrow().right {
-                                Some(right_node) => {
-                                    new_node.borrow_mut().left = Some(Rc::clone(&right_node));
-                                    current = Some(Rc::clone(&new_node));
-                                    tm = RightChild;
-                                    last_precedence = tokens[i].get_precedence();
-                                }
-                                _ => {
-                                    panic!("Expected the root to have a right node at this point.")
-                                }
-                            }
-                            root_node.borrow_mut().right = Some(Rc::clone(&new_node));
-                        }
-                        _ => panic!("Expected the tree to be initialized at this point."),
-                    }
-                }
-                Operator => {
-                    let new_node = Rc::new(RefCell::new(TreeNode::new(tokens[i].clone(), 0)));
-                    let root_node = ast.root.unwrap();
-                    new_node.borrow_mut().left = Some(Rc::clone(&root_node));
-                    ast.root = Some(Rc::clone(&new_node));
-                    current = Some(Rc::clone(&new_node));
-                    tm = RightChild;
-                    last_precedence = tokens[i].get_precedence();
-                }
-                _ => panic!("Unhandled binary case: {tm:?}"),
-            },
-            _ => {
-                let new_node;
-                if tokens[i].variant == TokenVariant::LeftParen {
-                    let result = parse_sub_expression(tokens, &mut i);
-                    match result {
-                        Ok(n) => new_node = n,
-                        Err(e) => return Err(e),
-                    }
-                } else {
-                    new_node = Rc::new(RefCell::new(TreeNode::new(tokens[i].clone(), 0)));
-                }
-                match tm {
-                    Root => {
-                        ast.root = Some(Rc::clone(&new_node));
-                        current = Some(Rc::clone(&new_node));
-                    }
-                    RightChild => match (&ast.root, &current) {
-                        (Some(_), Some(current_node)) => {
-                            current_node.borrow_mut().right = Some(Rc::clone(&new_node));
-                            current = Some(Rc::clone(&new_node));
-                        }
-                        _ => {
-                            panic!("Expected the nodes to be populated at this point.")
-                        }
-                    },
-                    _ => {
-                        panic!("Unhandled regular case: {tm:?}")
-                    }
-                }
-                tm = Operator;
+/// Precedence climbing over the binary operators (see
+/// `Token::get_precedence()`), with recursive descent for the comma,
+/// assignments, conditionals, groups, unary operators, `**`, calls and
+/// functions.
+struct Parser<'a> {
+    tokens: &'a [Token],
+    index: usize,
+    unclosed: Vec<usize>,
+    /// Groups, unary operators and exponents currently being parsed
+    depth: usize,
+    max_depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> &'a Token {
+        &self.tokens[self.index.min(self.tokens.len() - 1)]
+    }
+
+    fn advance(&mut self) -> &'a Token {
+        let token = self.peek();
+        if token.variant != TokenVariant::Eof {
+            self.index += 1;
+        }
+        token
+    }
+
+    /// Consumes a token of the given kind, or reports what was expected.
+    fn consume(&mut self, variant: TokenVariant, expected: &'static str) -> Result<&'a Token, SyntaxError> {
+        let token = self.peek();
+        if token.variant != variant {
+            return Err(SyntaxError::new(SyntaxErrorVariant::Expect(token.line, token.lexeme.clone(), expected)));
+        }
+        Ok(self.advance())
+    }
+
+    /// Each level of recursion is checked, so that deep nesting is reported
+    /// before it exhausts the real stack.
+    fn descend(&mut self) -> Result<(), SyntaxError> {
+        self.depth += 1;
+        if self.depth > self.max_depth {
+            return Err(SyntaxError::new(SyntaxErrorVariant::StackOverflow(self.peek().line)));
+        }
+        Ok(())
+    }
+
+    /// What to report for a token that follows a complete operand but does
+    /// not continue the expression.
+    fn unexpected(&self) -> SyntaxError {
+        let t = self.peek();
+        match t.variant {
+            // Two operands in a row, as in `1 2`, or a stray `)`
+            TokenVariant::Number(_)
+            | TokenVariant::Integer(_)
+            | TokenVariant::String(_)
+            | TokenVariant::True
+            | TokenVariant::False
+            | TokenVariant::Nil
+            | TokenVariant::Identifier
+            | TokenVariant::Fun
+            | TokenVariant::LeftParen
+            | TokenVariant::RightBracket
+            | TokenVariant::RightParen => SyntaxError::new(SyntaxErrorVariant::ExpectEnd(t.line, t.lexeme.clone())),
+            _ => expect_expression(t),
+        }
+    }
+
+    /// `a, b` evaluates both and gives `b`. It binds loosest of all.
+    fn comma(&mut self) -> Result<Rc<RefCell<TreeNode>>, SyntaxError> {
+        let mut left = self.assignment()?;
+        while self.peek().variant == TokenVariant::Comma {
+            let token = self.advance();
+            let right = self.assignment()?;
+            let mut node = TreeNode::new(token.clone(), 0);
+            node.left = Some(left);
+            node.right = Some(right);
+            left = Rc::new(RefCell::new(node));
+        }
+        Ok(left)
+    }
+
+    /// Assignments group to the right, as in `a = b += 1`.
+    fn assignment(&mut self) -> Result<Rc<RefCell<TreeNode>>, SyntaxError> {
+        let target = self.conditional()?;
+        let token = self.peek();
+        if !token.is_assignment() {
+            return Ok(target);
+        }
+        self.advance();
+        assignable(&target, token)?;
+        self.descend()?;
+        let value = self.assignment()?;
+        self.depth -= 1;
+        let mut node = TreeNode::new(token.clone(), 0);
+        node.left = Some(target);
+        node.right = Some(value);
+        Ok(Rc::new(RefCell::new(node)))
+    }
+
+    /// `a ? b : c` is a `?` node whose right child is a `:` node holding both
+    /// branches. It groups to the right, so `a ? b : c ? d : e` needs no
+    /// parentheses.
+    fn conditional(&mut self) -> Result<Rc<RefCell<TreeNode>>, SyntaxError> {
+        let condition = self.expression(0)?;
+        if self.peek().variant != TokenVariant::Question {
+            return Ok(condition);
+        }
+        let question = self.advance();
+        self.descend()?;
+        let then_branch = self.assignment()?;
+        let colon = self.consume(TokenVariant::Colon, "':' after then branch of conditional expression")?;
+        let else_branch = self.assignment()?;
+        self.depth -= 1;
+        let mut branches = TreeNode::new(colon.clone(), 0);
+        branches.left = Some(then_branch);
+        branches.right = Some(else_branch);
+        let mut node = TreeNode::new(question.clone(), 0);
+        node.left = Some(condition);
+        node.right = Some(Rc::new(RefCell::new(branches)));
+        Ok(Rc::new(RefCell::new(node)))
+    }
+
+    fn expression(&mut self, min_precedence: u32) -> Result<Rc<RefCell<TreeNode>>, SyntaxError> {
+        let mut left = self.unary()?;
+        loop {
+            let token = self.peek();
+            if !token.is_binary_operator() || token.get_precedence() < min_precedence {
+                return Ok(left);
             }
+            self.advance();
+            let right = self.expression(token.get_precedence() + 1)?;
+            let mut node = TreeNode::new(token.clone(), 0);
+            node.left = Some(left);
+            node.right = Some(right);
+            left = Rc::new(RefCell::new(node));
         }
-        i += 1;
-    }
-    match &ast.root {
-        Some(r) => {
-            if !r.borrow().is_structured() {
-                errors.push(SyntaxError::new(SyntaxErrorVariant::ExpectExpression(
-                    tokens[i - 1].line,
-                    tokens[i - 1].lexeme.chars().last().unwrap(),
+    }
+
+    fn unary(&mut self) -> Result<Rc<RefCell<TreeNode>>, SyntaxError> {
+        let token = self.peek();
+        if !token.is_unary_operator() && !token.is_increment() {
+            return self.power();
+        }
+        self.advance();
+        self.descend()?;
+        let operand = self.unary()?;
+        self.depth -= 1;
+        if token.is_increment() {
+            assignable(&operand, token)?;
+        }
+        let mut node = TreeNode::new(token.clone(), 0);
+        node.right = Some(operand);
+        Ok(Rc::new(RefCell::new(node)))
+    }
+
+    /// `**` binds tighter than unary operators, so `-2 ** 2` is `-(2 ** 2)`,
+    /// and groups to the right.
+    fn power(&mut self) -> Result<Rc<RefCell<TreeNode>>, SyntaxError> {
+        let base = self.postfix()?;
+        if self.peek().variant != TokenVariant::StarStar {
+            return Ok(base);
+        }
+        let token = self.advance();
+        self.descend()?;
+        // A unary operand, so that `2 ** -1` needs no parentheses
+        let exponent = self.unary()?;
+        self.depth -= 1;
+        let mut node = TreeNode::new(token.clone(), 0);
+        node.left = Some(base);
+        node.right = Some(exponent);
+        Ok(Rc::new(RefCell::new(node)))
+    }
+
+    /// Calls, indexes, methods, and `x++` and `x--`, whose operand is kept in
+    /// the left child.
+    fn postfix(&mut self) -> Result<Rc<RefCell<TreeNode>>, SyntaxError> {
+        let mut operand = self.primary()?;
+        loop {
+            let token = self.peek();
+            if token.variant == TokenVariant::LeftParen {
+                operand = self.call(operand)?;
+            } else if token.variant == TokenVariant::LeftBracket {
+                operand = self.index(operand)?;
+            } else if token.variant == TokenVariant::Dot {
+                self.advance();
+                let name = self.consume(TokenVariant::Identifier, "property name after '.'")?;
+                let mut node = TreeNode::new(token.clone(), 0);
+                node.left = Some(operand);
+                node.right = Some(Rc::new(RefCell::new(TreeNode::new(name.clone(), 0))));
+                operand = Rc::new(RefCell::new(node));
+            } else if token.is_increment() {
+                self.advance();
+                assignable(&operand, token)?;
+                let mut node = TreeNode::new(token.clone(), 0);
+                node.left = Some(operand);
+                operand = Rc::new(RefCell::new(node));
+            } else {
+                return Ok(operand);
+            }
+        }
+    }
+
+    /// `callee(a, b)` is a `(` node with the callee on the left and the
+    /// arguments on the right. Arguments are parsed above the comma operator,
+    /// so `f((a, b))` passes a single one.
+    fn call(&mut self, callee: Rc<RefCell<TreeNode>>) -> Result<Rc<RefCell<TreeNode>>, SyntaxError> {
+        if self.unclosed.binary_search(&self.index).is_ok() {
+            return Err(SyntaxError::new(SyntaxErrorVariant::UnmatchedParentheses));
+        }
+        let paren = self.advance();
+        self.descend()?;
+        let mut arguments = None;
+        let mut count = 0;
+        let mut comma = paren;
+        // A comma must be followed by another argument
+        while self.peek().variant != TokenVariant::RightParen || comma.variant == TokenVariant::Comma {
+            if count == MAX_ARGUMENTS {
+                let t = self.peek();
+                return Err(SyntaxError::new(SyntaxErrorVariant::TooMany(t.line, t.lexeme.clone(), "arguments")));
+            }
+            arguments = Some(push(arguments, comma, self.assignment()?));
+            count += 1;
+            if self.peek().variant != TokenVariant::Comma {
+                break;
+            }
+            comma = self.advance();
+        }
+        self.consume(TokenVariant::RightParen, "')' after arguments")?;
+        self.depth -= 1;
+        let mut node = TreeNode::new(paren.clone(), 0);
+        node.left = Some(callee);
+        node.right = arguments;
+        Ok(Rc::new(RefCell::new(node)))
+    }
+
+    /// `xs[i]` is a `[` node with the list on the left and the index on the
+    /// right.
+    fn index(&mut self, list: Rc<RefCell<TreeNode>>) -> Result<Rc<RefCell<TreeNode>>, SyntaxError> {
+        let bracket = self.advance();
+        self.descend()?;
+        let index = self.comma()?;
+        self.consume(TokenVariant::RightBracket, "']' after index")?;
+        self.depth -= 1;
+        let mut node = TreeNode::new(bracket.clone(), 0);
+        node.left = Some(list);
+        node.right = Some(index);
+        Ok(Rc::new(RefCell::new(node)))
+    }
+
+    /// `[a, b]` is a `[` node with the items on the right, chained like the
+    /// arguments of a call.
+    fn list(&mut self) -> Result<Rc<RefCell<TreeNode>>, SyntaxError> {
+        let bracket = self.advance();
+        self.descend()?;
+        let mut items = None;
+        let mut comma = bracket;
+        while self.peek().variant != TokenVariant::RightBracket || comma.variant == TokenVariant::Comma {
+            items = Some(push(items, comma, self.assignment()?));
+            if self.peek().variant != TokenVariant::Comma {
+                break;
+            }
+            comma = self.advance();
+        }
+        self.consume(TokenVariant::RightBracket, "']' after list items")?;
+        self.depth -= 1;
+        let mut node = TreeNode::new(bracket.clone(), 0);
+        node.right = items;
+        Ok(Rc::new(RefCell::new(node)))
+    }
+
+    /// Whether the `(` at the current token opens the parameters of an arrow
+    /// function, as in `(a, b) => a + b`, rather than a group.
+    fn at_arrow(&self) -> bool {
+        let variant = |i: usize| self.tokens.get(i).map(|t| &t.variant);
+        let mut i = self.index + 1;
+        if variant(i) == Some(&TokenVariant::Identifier) {
+            i += 1;
+            while variant(i) == Some(&TokenVariant::Comma) && variant(i + 1) == Some(&TokenVariant::Identifier) {
+                i += 2;
+            }
+        }
+        variant(i) == Some(&TokenVariant::RightParen) && variant(i + 1) == Some(&TokenVariant::EqualGreater)
+    }
+
+    /// `(a, b)`, chained like the arguments of a call.
+    fn parameters(&mut self) -> Result<Option<Rc<RefCell<TreeNode>>>, SyntaxError> {
+        let mut comma = self.consume(TokenVariant::LeftParen, "'(' after 'fun'")?;
+        let mut parameters = None;
+        let mut names: Vec<&str> = Vec::new();
+        while self.peek().variant != TokenVariant::RightParen || comma.variant == TokenVariant::Comma {
+            let t = self.peek();
+            if names.len() == MAX_ARGUMENTS {
+                return Err(SyntaxError::new(SyntaxErrorVariant::TooMany(t.line, t.lexeme.clone(), "parameters")));
+            }
+            let name = self.consume(TokenVariant::Identifier, "parameter name")?;
+            if names.contains(&name.lexeme.as_str()) {
+                return Err(SyntaxError::new(SyntaxErrorVariant::DuplicateParameter(
+                    name.line,
+                    name.lexeme.clone(),
                 )));
             }
+            names.push(&name.lexeme);
+            parameters = Some(push(parameters, comma, Rc::new(RefCell::new(TreeNode::new(name.clone(), 0)))));
+            if self.peek().variant != TokenVariant::Comma {
+                break;
+            }
+            comma = self.advance();
+        }
+        self.consume(TokenVariant::RightParen, "')' after parameters")?;
+        Ok(parameters)
+    }
+
+    /// `fun (a, b) { return a + b; }` is a `fun` node with the parameters on
+    /// the left and the returned expression, if any, on the right. The body
+    /// can only hold that one `return`, since there are no other statements.
+    fn function(&mut self) -> Result<Rc<RefCell<TreeNode>>, SyntaxError> {
+        let fun = self.advance();
+        self.descend()?;
+        let parameters = self.parameters()?;
+        self.consume(TokenVariant::LeftBrace, "'{' before function body")?;
+        let mut body = None;
+        if self.peek().variant == TokenVariant::Return {
+            self.advance();
+            if self.peek().variant != TokenVariant::Semicolon {
+                body = Some(self.comma()?);
+            }
+            self.consume(TokenVariant::Semicolon, "';' after return value")?;
+        }
+        self.consume(TokenVariant::RightBrace, "'}' after block")?;
+        self.depth -= 1;
+        let mut node = TreeNode::new(fun.clone(), 0);
+        node.left = parameters;
+        node.right = body;
+        Ok(Rc::new(RefCell::new(node)))
+    }
+
+    /// `(a, b) => a + b`, a `=>` node shaped like a `fun` one. The body goes
+    /// as far as an assignment would, so `(a) => a, b` is a comma expression.
+    fn arrow(&mut self) -> Result<Rc<RefCell<TreeNode>>, SyntaxError> {
+        let parameters = self.parameters()?;
+        let arrow = self.advance();
+        self.descend()?;
+        let body = self.assignment()?;
+        self.depth -= 1;
+        let mut node = TreeNode::new(arrow.clone(), 0);
+        node.left = parameters;
+        node.right = Some(body);
+        Ok(Rc::new(RefCell::new(node)))
+    }
+
+    fn primary(&mut self) -> Result<Rc<RefCell<TreeNode>>, SyntaxError> {
+        let token = self.peek();
+        match token.variant {
+            TokenVariant::LeftParen if self.at_arrow() => self.arrow(),
+            TokenVariant::LeftParen => {
+                if self.unclosed.binary_search(&self.index).is_ok() {
+                    return Err(SyntaxError::new(SyntaxErrorVariant::UnmatchedParentheses));
+                }
+                self.advance();
+                self.descend()?;
+                let inner = self.comma()?;
+                self.depth -= 1;
+                if self.peek().variant != TokenVariant::RightParen {
+                    return Err(self.unexpected());
+                }
+                self.advance();
+                inner.borrow_mut().group_count += 1;
+                Ok(inner)
+            }
+            TokenVariant::Number(_)
+            | TokenVariant::Integer(_)
+            | TokenVariant::String(_)
+            | TokenVariant::True
+            | TokenVariant::False
+            | TokenVariant::Nil
+            | TokenVariant::Identifier => {
+                self.advance();
+                Ok(Rc::new(RefCell::new(TreeNode::new(token.clone(), 0))))
+            }
+            TokenVariant::Fun => self.function(),
+            TokenVariant::LeftBracket => self.list(),
+            // There are no loops yet, so these are always misplaced
+            TokenVariant::Break | TokenVariant::Continue => Err(SyntaxError::new(SyntaxErrorVariant::OutsideLoop(
+                token.line,
+                token.lexeme.clone(),
+            ))),
+            _ => Err(expect_expression(token)),
         }
-        None => errors.push(SyntaxError::new(SyntaxErrorVariant::UnmatchedParentheses)),
     }
-    if !errors.is_empty() {
-        Err(errors)
-    } else {
-        Ok(ast)
+}
+
+pub fn parse(tokens: &[Token]) -> Result<Tree, Vec<SyntaxError>> {
+    parse_with_max_depth(tokens, MAX_DEPTH)
+}
+
+pub fn parse_with_max_depth(tokens: &[Token], max_depth: usize) -> Result<Tree, Vec<SyntaxError>> {
+    let eof = [Token::from((TokenVariant::Eof, 1))];
+    let mut parser = Parser {
+        tokens: if tokens.is_empty() { &eof } else { tokens },
+        index: 0,
+        unclosed: unclosed_parentheses(tokens),
+        depth: 0,
+        max_depth,
+    };
+    let root = parser.comma().map_err(|e| vec![e])?;
+    if parser.peek().variant != TokenVariant::Eof {
+        return Err(vec![parser.unexpected()]);
+    }
+    if let Some(line) = too_deep(&root, max_depth) {
+        return Err(vec![SyntaxError::new(SyntaxErrorVariant::StackOverflow(line))]);
     }
+    Ok(Tree { root: Some(root) })
 }
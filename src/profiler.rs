@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+use std::rc::Rc;
+
+use crate::evaluator::{Environment, Function, Hooks, RuntimeError};
+use crate::lexer::Token;
+use crate::parser::TreeNode;
+
+/// How many rows of each table the report shows.
+const TOP: usize = 10;
+
+#[derive(Default, Clone, Copy)]
+struct Stats {
+    calls: u32,
+    self_time: Duration,
+    total_time: Duration,
+}
+
+struct Frame {
+    line: u32,
+    started: Instant,
+    children: Duration,
+}
+
+/// A function that was called and has not returned, or the script itself.
+struct Call {
+    name: String,
+    started: Instant,
+    /// Time spent in the functions it called
+    callees: Duration,
+}
+
+/// Times every node the evaluator visits and every function it calls. Time
+/// spent in a node minus the time spent in its operands is the self time of
+/// its line, and time spent in a function minus the time spent in the
+/// functions it calls is the self time of the function. Functions are named
+/// `lambda@<line>` after where they are written, and the top level is `script`.
+pub struct Profiler {
+    frames: Vec<Frame>,
+    calls: Vec<Call>,
+    functions: HashMap<String, Stats>,
+    lines: HashMap<u32, Stats>,
+    stacks: HashMap<String, Duration>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self {
+            frames: Vec::new(),
+            calls: Vec::new(),
+            functions: HashMap::new(),
+            lines: HashMap::new(),
+            stacks: HashMap::new(),
+        }
+    }
+
+    /// The top rows of each table, slowest self time first.
+    pub fn report(&self) -> String {
+        let mut out = String::new();
+        let functions: Vec<(String, Stats)> = self.functions.iter().map(|(f, s)| (f.clone(), *s)).collect();
+        let lines: Vec<(String, Stats)> = self.lines.iter().map(|(l, s)| (format!("line {}", l), *s)).collect();
+        for (title, mut rows) in [("function", functions), ("line", lines)] {
+            rows.sort_by(|a, b| b.1.self_time.cmp(&a.1.self_time).then_with(|| a.0.cmp(&b.0)));
+            let _ = writeln!(out, "{:>8} {:>12} {:>12}  {}", "calls", "self ms", "total ms", title);
+            for (name, stats) in rows.iter().take(TOP) {
+                let _ = writeln!(
+                    out,
+                    "{:>8} {:>12.3} {:>12.3}  {}",
+                    stats.calls,
+                    stats.self_time.as_secs_f64() * 1000.0,
+                    stats.total_time.as_secs_f64() * 1000.0,
+                    name
+                );
+            }
+            out.push('\n');
+        }
+        out.pop();
+        out
+    }
+
+    /// Self time in nanoseconds per call stack, one `script;lambda@1;lambda@4
+    /// count` line each, as read by flamegraph tools.
+    pub fn collapsed_stacks(&self) -> String {
+        let mut stacks: Vec<(&String, &Duration)> = self.stacks.iter().collect();
+        stacks.sort();
+        stacks
+            .iter()
+            .map(|(stack, time)| format!("{} {}\n", stack, time.as_nanos()))
+            .collect()
+    }
+}
+
+impl Profiler {
+    fn enter_call(&mut self, name: String) {
+        self.calls.push(Call {
+            name,
+            started: Instant::now(),
+            callees: Duration::ZERO,
+        });
+    }
+
+    fn leave_call(&mut self) {
+        let Some(call) = self.calls.pop() else {
+            return;
+        };
+        let total = call.started.elapsed();
+        let self_time = total.saturating_sub(call.callees);
+        let stack: Vec<&str> = self.calls.iter().chain([&call]).map(|c| c.name.as_str()).collect();
+        *self.stacks.entry(stack.join(";")).or_default() += self_time;
+        let recursive = self.calls.iter().any(|c| c.name == call.name);
+        let stats = self.functions.entry(call.name).or_default();
+        stats.calls += 1;
+        stats.self_time += self_time;
+        // A recursive call is already inside the total of the outer one
+        if !recursive {
+            stats.total_time += total;
+        }
+        if let Some(caller) = self.calls.last_mut() {
+            caller.callees += total;
+        }
+    }
+}
+
+impl Hooks for Profiler {
+    fn enter(&mut self, node: &TreeNode, _env: &Rc<Environment>, depth: usize) {
+        if depth == 0 {
+            self.enter_call("script".to_owned());
+        }
+        self.frames.push(Frame {
+            line: node.value.line,
+            started: Instant::now(),
+            children: Duration::ZERO,
+        });
+    }
+
+    fn leave(&mut self, _node: &TreeNode, _env: &Rc<Environment>, depth: usize, _result: &Result<Token, Vec<RuntimeError>>) {
+        let Some(frame) = self.frames.pop() else {
+            return;
+        };
+        let total = frame.started.elapsed();
+        let line = self.lines.entry(frame.line).or_default();
+        line.calls += 1;
+        line.self_time += total.saturating_sub(frame.children);
+        // Nested nodes on the same line would otherwise count the same time twice
+        if self.frames.iter().all(|f| f.line != frame.line) {
+            line.total_time += total;
+        }
+        if let Some(parent) = self.frames.last_mut() {
+            parent.children += total;
+        }
+        if depth == 0 {
+            self.leave_call();
+        }
+    }
+
+    fn enter_function(&mut self, function: &Function) {
+        self.enter_call(function.label());
+    }
+
+    fn leave_function(&mut self, _function: &Function) {
+        self.leave_call();
+    }
+}
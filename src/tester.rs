@@ -0,0 +1,305 @@
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+
+use crate::evaluator;
+use crate::json::Json;
+use crate::lexer;
+use crate::lsp;
+use crate::optimizer;
+use crate::parser;
+
+const EXPECT_OUTPUT: &str = "// expect: ";
+const EXPECT_RUNTIME_ERROR: &str = "// expect runtime error: ";
+const EXPECT_ERROR: &str = "// expect error: ";
+const EXPECT_EXIT: &str = "// expect exit: ";
+const STRICT_MODE: &str = "// mode: strict";
+const RUN_COMMAND: &str = "// run: ";
+
+/// What running a script printed and how it exited.
+#[derive(Debug, PartialEq)]
+pub struct Outcome {
+    pub stdout: Vec<String>,
+    pub stderr: Vec<String>,
+    pub exit_code: i32,
+}
+
+/// Runs a script the way the `evaluate` command does, but in-process, with
+/// `--strict` if the script has a `// mode: strict` comment. A panic is
+/// reported on stderr with exit code 101, like an uncaught one.
+pub fn run(source: &str) -> Outcome {
+    let lines = |errors: Vec<String>| errors.join("\n").lines().map(str::to_owned).collect();
+    let outcome = panic::catch_unwind(|| {
+        let (tokens, errors) = lexer::tokenize(source);
+        if let Some(e) = errors {
+            return (Vec::new(), e.iter().map(|e| e.to_string()).collect(), 65);
+        }
+        let mut ast = match parser::parse(&tokens) {
+            Ok(ast) => ast,
+            Err(e) => {
+                let code = if e.iter().any(|e| e.is_stack_overflow()) { 70 } else { 65 };
+                return (Vec::new(), e.iter().map(|e| e.to_string()).collect(), code);
+            }
+        };
+        let interpreter = evaluator::Interpreter {
+            strict: source.lines().any(|line| line.contains(STRICT_MODE)),
+            ..evaluator::Interpreter::new()
+        };
+        optimizer::optimize(&mut ast, &interpreter);
+        match interpreter.evaluate(&ast) {
+            Ok(output) => (vec![output.value_print()], Vec::new(), 0),
+            Err(e) => (Vec::new(), e.iter().map(|e| e.to_string()).collect(), 70),
+        }
+    });
+    match outcome {
+        Ok((stdout, stderr, exit_code)) => Outcome {
+            stdout: lines(stdout),
+            stderr: lines(stderr),
+            exit_code,
+        },
+        Err(payload) => {
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            Outcome {
+                stdout: Vec::new(),
+                stderr: vec![format!("panicked: {}", message)],
+                exit_code: 101,
+            }
+        }
+    }
+}
+
+/// Runs `command` (a command name and its options) on `file` in a child
+/// process, the way a user would, so that even a crash only fails the test.
+/// Its input comes from the `.in` file next to `file`, if there is one, with
+/// `$FILE` replaced by the path and `$SOURCE` by the script escaped for JSON.
+/// For `lsp` and `dap` each line of input is a message, framed on the way in,
+/// and each message out is printed on a line of its own. The path is written
+/// `$FILE` in the output as well.
+fn run_command(file: &Path, source: &str, command: &str) -> Outcome {
+    let mut words = command.split_whitespace();
+    let name = words.next().unwrap_or_default();
+    let framed = matches!(name, "lsp" | "dap");
+    let path = file.display().to_string();
+    let escaped = Json::from(source).to_string();
+    let mut input = fs::read_to_string(file.with_extension("in"))
+        .unwrap_or_default()
+        .replace("$FILE", &path)
+        .replace("$SOURCE", &escaped[1..escaped.len() - 1]);
+    if framed {
+        input = input
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|message| format!("Content-Length: {}\r\n\r\n{}", message.len(), message))
+            .collect();
+    }
+    let child = env::current_exe().and_then(|exe| {
+        Command::new(exe)
+            .arg(name)
+            .arg(&path)
+            .args(words)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+    });
+    let output = child.and_then(|mut child| {
+        // Written from another thread, in case the child fills its output pipe first
+        let mut stdin = child.stdin.take();
+        let writer = thread::spawn(move || stdin.as_mut().map(|s| s.write_all(input.as_bytes())));
+        let output = child.wait_with_output();
+        let _ = writer.join();
+        output
+    });
+    let output = match output {
+        Ok(output) => output,
+        Err(e) => {
+            return Outcome {
+                stdout: Vec::new(),
+                stderr: vec![format!("could not run {}: {}", name, e)],
+                exit_code: -1,
+            }
+        }
+    };
+    let absolute = fs::canonicalize(file).map_or(path.clone(), |p| p.display().to_string());
+    let lines = |text: &str| -> Vec<String> {
+        let text = text.replace(&absolute, "$FILE").replace(&path, "$FILE");
+        text.lines().map(str::to_owned).collect()
+    };
+    let stdout = if framed {
+        let mut messages = io::Cursor::new(output.stdout);
+        let mut stdout = Vec::new();
+        while let Ok(Some(message)) = lsp::read_message(&mut messages) {
+            stdout.extend(lines(&message.to_string()));
+        }
+        stdout
+    } else {
+        lines(&String::from_utf8_lossy(&output.stdout))
+    };
+    Outcome {
+        stdout,
+        stderr: lines(&String::from_utf8_lossy(&output.stderr)),
+        // A process killed by a signal, such as the abort of a real stack overflow, has no code
+        exit_code: output.status.code().unwrap_or(-1),
+    }
+}
+
+/// The outcome a script describes in its comments:
+///
+/// - `// expect: <line>` for each line printed,
+/// - `// expect runtime error: <message>` for a runtime error on that line,
+/// - `// [line N] Error...` or `// Error...` (on the line of the comment) for
+///   each lexical or syntax error, or `// expect error: <text>` for errors
+///   printed without a line,
+/// - `// expect exit: <code>` for an exit code other than the one the other
+///   comments imply.
+pub fn expectations(source: &str) -> Outcome {
+    let mut expected = Outcome {
+        stdout: Vec::new(),
+        stderr: Vec::new(),
+        exit_code: 0,
+    };
+    let mut exit_code = None;
+    for (i, line) in source.lines().enumerate() {
+        let number = i + 1;
+        if let Some((_, code)) = line.split_once(EXPECT_EXIT) {
+            exit_code = code.trim().parse().ok();
+        } else if let Some((_, output)) = line.split_once(EXPECT_OUTPUT) {
+            expected.stdout.push(output.to_owned());
+        } else if let Some((_, message)) = line.split_once(EXPECT_RUNTIME_ERROR) {
+            expected.stderr = vec![message.to_owned(), format!("[line {}] ", number)];
+            expected.exit_code = 70;
+        } else if let Some((_, error)) = line.split_once(EXPECT_ERROR) {
+            expected.stderr.push(error.to_owned());
+            expected.exit_code = 65;
+        } else if let Some((_, error)) = line.split_once("// [line ") {
+            expected.stderr.push(format!("[line {}", error));
+            expected.exit_code = 65;
+        } else if let Some((_, error)) = line.split_once("// Error") {
+            expected.stderr.push(format!("[line {}] Error{}", number, error));
+            expected.exit_code = 65;
+        }
+    }
+    expected.exit_code = exit_code.unwrap_or(expected.exit_code);
+    expected
+}
+
+/// What `file` should print and how it should exit. Output kept in the `.out`
+/// and `.err` files next to it replaces the comments for stdout and stderr,
+/// for commands whose output would be awkward to write in comments.
+fn expected_outcome(file: &Path, source: &str) -> Outcome {
+    let mut expected = expectations(source);
+    let lines = |text: String| text.lines().map(str::to_owned).collect();
+    if let Ok(stdout) = fs::read_to_string(file.with_extension("out")) {
+        expected.stdout = lines(stdout);
+    }
+    if let Ok(stderr) = fs::read_to_string(file.with_extension("err")) {
+        expected.stderr = lines(stderr);
+    }
+    expected
+}
+
+/// Differences between what was expected and what happened, one per line.
+/// Only the start of a stack trace is checked, unless `whole_stderr`.
+fn compare(expected: &Outcome, actual: &Outcome, whole_stderr: bool) -> Vec<String> {
+    let mut diff = Vec::new();
+    let mut lines = |name: &str, expected: &[String], actual: &[String], matches: &dyn Fn(&str, &str) -> bool| {
+        for i in 0..expected.len().max(actual.len()) {
+            match (expected.get(i), actual.get(i)) {
+                (Some(e), Some(a)) if matches(e, a) => {}
+                (e, a) => {
+                    diff.push(format!("  {} line {}:", name, i + 1));
+                    if let Some(e) = e {
+                        diff.push(format!("  - {}", e));
+                    }
+                    if let Some(a) = a {
+                        diff.push(format!("  + {}", a));
+                    }
+                }
+            }
+        }
+    };
+    lines("stdout", &expected.stdout, &actual.stdout, &|e, a| e == a);
+    if expected.exit_code == 70 && !whole_stderr {
+        // Only the message and the line of the innermost frame are checked,
+        // the frame up to the space after `[line N]`
+        let actual: Vec<String> = actual.stderr.iter().take(2).cloned().collect();
+        lines("stderr", &expected.stderr, &actual, &|e, a| e == a || (e.starts_with("[line ") && a.starts_with(e)));
+    } else {
+        lines("stderr", &expected.stderr, &actual.stderr, &|e, a| e == a);
+    }
+    if expected.exit_code != actual.exit_code {
+        diff.push(format!("  exit code: expected {}, got {}", expected.exit_code, actual.exit_code));
+    }
+    diff
+}
+
+fn lox_files(path: &Path, files: &mut Vec<PathBuf>) {
+    if path.is_file() {
+        files.push(path.to_owned());
+        return;
+    }
+    let mut entries: Vec<PathBuf> = fs::read_dir(path)
+        .map(|dir| dir.flatten().map(|entry| entry.path()).collect())
+        .unwrap_or_default();
+    entries.sort();
+    for entry in entries {
+        if entry.is_dir() {
+            lox_files(&entry, files);
+        } else if entry.extension().is_some_and(|e| e == "lox") {
+            files.push(entry);
+        }
+    }
+}
+
+/// Runs every `.lox` file under `path` and prints a summary. Returns the
+/// process exit code: 1 if any test failed, 66 if there was nothing to run.
+pub fn run_tests(path: &str) -> i32 {
+    let mut files = Vec::new();
+    lox_files(Path::new(path), &mut files);
+    if files.is_empty() {
+        eprintln!("No .lox files found in {}", path);
+        return 66;
+    }
+    // Panics are part of the outcome, the default hook would only add noise
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let mut failed = 0;
+    for file in files.iter() {
+        let source = match fs::read_to_string(file) {
+            Ok(source) => source,
+            Err(_) => {
+                println!("FAIL {}\n  could not read the file", file.display());
+                failed += 1;
+                continue;
+            }
+        };
+        let command = source.lines().find_map(|line| line.split_once(RUN_COMMAND).map(|(_, c)| c.trim()));
+        let actual = match command {
+            Some(command) => run_command(file, &source, command),
+            None => run(&source),
+        };
+        let whole_stderr = file.with_extension("err").exists();
+        let diff = compare(&expected_outcome(file, &source), &actual, whole_stderr);
+        if diff.is_empty() {
+            println!("PASS {}", file.display());
+        } else {
+            println!("FAIL {}\n{}", file.display(), diff.join("\n"));
+            failed += 1;
+        }
+    }
+    panic::set_hook(default_hook);
+    println!("{} passed, {} failed.", files.len() - failed, failed);
+    if failed > 0 {
+        1
+    } else {
+        0
+    }
+}